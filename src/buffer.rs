@@ -1,49 +1,495 @@
-use std::{
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::{
+    borrow::Borrow,
     cell::UnsafeCell,
+    cmp::Ordering,
     fmt::{self, Debug, Formatter},
+    hash::{Hash, Hasher},
     mem::{self, MaybeUninit},
-    ptr::NonNull,
-    rc::Rc,
+    ops::{Deref, Range},
+    ptr::{self, NonNull},
     slice,
+    str::{self, Utf8Error},
 };
 
 /// A reference to a buffer and a slice within it.
-pub struct Bytes {
-    pub(crate) buf: Buf,
-    pub(crate) slice: NonNull<[u8]>,
+pub struct Bytes<T = u8> {
+    pub(crate) buf: Buf<T>,
+    pub(crate) slice: NonNull<[T]>,
 }
 
-impl Bytes {
-    pub fn slice(&self) -> &[u8] {
+impl<T: Copy> Bytes<T> {
+    /// Constructs a reference to a range of the portion of the buffer which
+    /// was filled when the handle was borrowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the range is out of bounds of the filled portion.
+    pub fn from_buf(buf: Buf<T>, range: Range<usize>) -> Bytes<T> {
+        let slice = buf.as_slice()[range].into();
+        Bytes { buf, slice }
+    }
+
+    pub fn slice(&self) -> &[T] {
         unsafe { &*self.slice.as_ptr() }
     }
 
-    pub fn buf(&self) -> Buf {
+    pub fn len(&self) -> usize {
+        self.slice.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slice.is_empty()
+    }
+
+    /// Returns a sub-range of the slice, which retains the same buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the range is out of bounds of the slice.
+    pub fn subslice(&self, range: Range<usize>) -> Bytes<T> {
+        Bytes {
+            buf: self.buf.clone(),
+            slice: self.slice()[range].into(),
+        }
+    }
+
+    /// Splits the slice in two at an index, where both halves retain the same
+    /// buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(&self, mid: usize) -> (Bytes<T>, Bytes<T>) {
+        let (left, right) = self.slice().split_at(mid);
+        let left = Bytes {
+            buf: self.buf.clone(),
+            slice: left.into(),
+        };
+        let right = Bytes {
+            buf: self.buf.clone(),
+            slice: right.into(),
+        };
+        (left, right)
+    }
+
+    /// Returns whether the slice starts with the prefix.
+    pub fn starts_with(&self, prefix: &[T]) -> bool
+    where
+        T: PartialEq,
+    {
+        self.slice().starts_with(prefix)
+    }
+
+    /// Returns whether the slice ends with the suffix.
+    pub fn ends_with(&self, suffix: &[T]) -> bool
+    where
+        T: PartialEq,
+    {
+        self.slice().ends_with(suffix)
+    }
+
+    /// Returns the slice after the prefix, which retains the same buffer, or
+    /// `None` if it does not start with the prefix.
+    pub fn strip_prefix(&self, prefix: &[T]) -> Option<Bytes<T>>
+    where
+        T: PartialEq,
+    {
+        self.starts_with(prefix)
+            .then(|| self.subslice(prefix.len()..self.len()))
+    }
+
+    /// Returns the slice before the suffix, which retains the same buffer, or
+    /// `None` if it does not end with the suffix.
+    pub fn strip_suffix(&self, suffix: &[T]) -> Option<Bytes<T>>
+    where
+        T: PartialEq,
+    {
+        self.ends_with(suffix)
+            .then(|| self.subslice(0..self.len() - suffix.len()))
+    }
+
+    /// Copies the parts, in order, into a new buffer which exactly fits them,
+    /// and returns a reference to all of it. It only retains the new buffer.
+    pub fn concat(parts: &[Bytes<T>]) -> Bytes<T> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        let mut buf = BufMut::new(len);
+        for part in parts {
+            buf.append(part);
+        }
+        buf.consume_bytes(len)
+    }
+
+    /// Returns an iterator over sub-slices of `size` elements, each of which
+    /// retains the same buffer. Like `[T]::chunks`, the last may be shorter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn chunks(&self, size: usize) -> Chunks<'_, T> {
+        assert!(size != 0, "chunk size must be non-zero");
+        Chunks {
+            bytes: self,
+            size,
+            start: 0,
+        }
+    }
+
+    /// Copies the slice into an owned vec.
+    pub fn to_vec(&self) -> Vec<T> {
+        self.slice().to_vec()
+    }
+
+    /// Copies the slice into an owned vec and releases the buffer.
+    pub fn into_owned(self) -> Vec<T> {
+        self.to_vec()
+    }
+
+    pub fn buf(&self) -> Buf<T> {
         self.buf.clone()
     }
+
+    /// Returns the offset of the slice from the start of its buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is zero-sized.
+    pub fn buf_offset(&self) -> usize {
+        let base = self.buf.buf.get() as *const T;
+        // SAFETY: The slice is within the buffer.
+        unsafe { self.slice.as_ptr().cast::<T>().offset_from_unsigned(base) }
+    }
+
+    /// Returns the range of the slice within its buffer, so that
+    /// `bytes.buf().as_slice()[bytes.buf_range()]` is the slice.
+    pub fn buf_range(&self) -> Range<usize> {
+        let offset = self.buf_offset();
+        offset..offset + self.len()
+    }
+}
+
+impl Bytes {
+    /// Copies the slice into a `bytes::Bytes` and releases the buffer.
+    ///
+    /// It is copied, since a `bytes::Bytes` can be sent to other threads, but
+    /// the buffer is reference counted with `Rc`.
+    #[cfg(feature = "bytes")]
+    pub fn into_bytes_crate(self) -> bytes::Bytes {
+        bytes::Bytes::copy_from_slice(self.slice())
+    }
+
+    /// Returns the slice with leading and trailing ASCII whitespace removed,
+    /// which retains the same buffer.
+    pub fn trim_ascii(&self) -> Bytes {
+        self.trim_ascii_start().trim_ascii_end()
+    }
+
+    /// Returns the slice with leading ASCII whitespace removed, which retains
+    /// the same buffer.
+    pub fn trim_ascii_start(&self) -> Bytes {
+        let start = self.len() - self.slice().trim_ascii_start().len();
+        self.subslice(start..self.len())
+    }
+
+    /// Returns the slice with trailing ASCII whitespace removed, which retains
+    /// the same buffer.
+    pub fn trim_ascii_end(&self) -> Bytes {
+        let end = self.slice().trim_ascii_end().len();
+        self.subslice(0..end)
+    }
+
+    /// Returns an iterator over the sub-slices separated by the delimiter,
+    /// each of which retains the same buffer. Like `[u8]::split`, it yields
+    /// one more sub-slice than there are delimiters.
+    pub fn split(&self, delim: u8) -> Split<'_> {
+        Split {
+            bytes: self,
+            delim,
+            start: Some(0),
+        }
+    }
+}
+
+/// Reads from the front of the slice and advances past the bytes read, like
+/// `&[u8]`. The buffer is retained, so a clone taken before reading still
+/// covers the whole slice.
+#[cfg(feature = "std")]
+impl std::io::Read for Bytes {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.len().min(out.len());
+        out[..len].copy_from_slice(&self.slice()[..len]);
+        self.slice = self.slice()[len..].into();
+        Ok(len)
+    }
+}
+
+/// Advances past the front of the slice, like the `Read` impl, for APIs which
+/// expect a `bytes::Buf`.
+#[cfg(feature = "bytes")]
+impl bytes::Buf for Bytes {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.slice()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(
+            cnt <= self.len(),
+            "cannot advance past the end of the slice"
+        );
+        self.slice = self.slice()[cnt..].into();
+    }
+}
+
+/// An iterator over the sub-slices of a [`Bytes`], which are separated by a
+/// delimiter. Created by [`Bytes::split`].
+pub struct Split<'a> {
+    bytes: &'a Bytes,
+    delim: u8,
+    /// The start of the next sub-slice or `None`, once the last has been
+    /// yielded.
+    start: Option<usize>,
+}
+
+impl Iterator for Split<'_> {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        let start = self.start?;
+        let end = match self.bytes[start..].iter().position(|&b| b == self.delim) {
+            Some(i) => {
+                self.start = Some(start + i + 1);
+                start + i
+            }
+            None => {
+                self.start = None;
+                self.bytes.len()
+            }
+        };
+        Some(self.bytes.subslice(start..end))
+    }
+}
+
+/// An iterator over fixed-size sub-slices of a [`Bytes`]. Created by
+/// [`Bytes::chunks`].
+pub struct Chunks<'a, T = u8> {
+    bytes: &'a Bytes<T>,
+    size: usize,
+    /// The start of the next chunk.
+    start: usize,
+}
+
+impl<T: Copy> Iterator for Chunks<'_, T> {
+    type Item = Bytes<T>;
+
+    fn next(&mut self) -> Option<Bytes<T>> {
+        if self.start == self.bytes.len() {
+            return None;
+        }
+        let end = self.start.saturating_add(self.size).min(self.bytes.len());
+        let chunk = self.bytes.subslice(self.start..end);
+        self.start = end;
+        Some(chunk)
+    }
+}
+
+/// Clones the reference, which shares the same region of the same buffer.
+impl<T> Clone for Bytes<T> {
+    fn clone(&self) -> Self {
+        Bytes {
+            buf: self.buf.clone(),
+            slice: self.slice,
+        }
+    }
+}
+
+/// Indexing and slice methods are available through the slice, so `bytes[i]`
+/// and `&bytes[8..]` borrow from it. Use [`Bytes::subslice`] to retain a
+/// sub-range instead.
+impl<T: Copy> Deref for Bytes<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice()
+    }
+}
+
+impl<T: Copy> AsRef<[T]> for Bytes<T> {
+    fn as_ref(&self) -> &[T] {
+        self.slice()
+    }
+}
+
+impl<T: Copy> Borrow<[T]> for Bytes<T> {
+    fn borrow(&self) -> &[T] {
+        self.slice()
+    }
+}
+
+/// Compares by contents, unlike `Buf`, which compares by identity.
+impl<T: Copy + PartialEq> PartialEq for Bytes<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.slice() == other.slice()
+    }
+}
+
+impl<T: Copy + Eq> Eq for Bytes<T> {}
+
+impl<T: Copy + PartialOrd> PartialOrd for Bytes<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.slice().partial_cmp(other.slice())
+    }
+}
+
+impl<T: Copy + Ord> Ord for Bytes<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.slice().cmp(other.slice())
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq<[T]> for Bytes<T> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.slice() == other
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq<&[T]> for Bytes<T> {
+    fn eq(&self, other: &&[T]) -> bool {
+        self.slice() == *other
+    }
+}
+
+impl PartialEq<str> for Bytes {
+    fn eq(&self, other: &str) -> bool {
+        self.slice() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Bytes {
+    fn eq(&self, other: &&str) -> bool {
+        self.slice() == other.as_bytes()
+    }
+}
+
+/// Formats the slice as an escaped byte string, along with the address of its
+/// buffer, so references into the same buffer can be told apart.
+impl Debug for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bytes")
+            .field(
+                "slice",
+                &format_args!("\"{}\"", self.slice().escape_ascii()),
+            )
+            .field("buf", &Rc::as_ptr(&self.buf.buf).cast::<()>())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Copies the slice into an owned string, if it is valid UTF-8, and releases
+/// the buffer.
+impl TryFrom<Bytes> for String {
+    type Error = Utf8Error;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Utf8Error> {
+        str::from_utf8(bytes.slice()).map(String::from)
+    }
+}
+
+impl<T: Copy + Hash> Hash for Bytes<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.slice().hash(state);
+    }
+}
+
+/// Serializes the slice as bytes, without the buffer.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.slice())
+    }
 }
 
 /// A persistent, reference counted buffer. Once data is written, it cannot be
-/// overwritten. This limited version of the API does not allow accessing the
-/// contents directly from the buffer.
-#[derive(Clone)]
-pub struct Buf {
-    buf: Rc<UnsafeCell<[u8]>>,
+/// overwritten. Only the portion which was filled when the handle was borrowed
+/// can be accessed from it.
+///
+/// Even when a handle is the last one, its allocation cannot be reused by a
+/// `Vec`, since an `Rc` allocation also holds the reference counts before the
+/// data. Use `as_slice().to_vec()` to copy the data out.
+pub struct Buf<T = u8> {
+    buf: Rc<UnsafeCell<[MaybeUninit<T>]>>,
+    /// The filled length at the time this handle was borrowed.
+    filled: usize,
 }
 
-impl Debug for Buf {
+impl<T: Copy> Buf<T> {
+    /// Returns the portion of the buffer which had been filled when this
+    /// handle was borrowed.
+    pub fn as_slice(&self) -> &[T] {
+        let buf = self.buf.get();
+        unsafe { slice::from_raw_parts(buf as *mut T, self.filled) }
+    }
+
+    /// Returns the length of the whole buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.get().len()
+    }
+
+    /// Returns the length of the portion which had been filled when this
+    /// handle was borrowed.
+    pub fn filled_len(&self) -> usize {
+        self.filled
+    }
+
+    /// Compares the filled portions of the buffers by contents. Unlike `==`,
+    /// which compares by identity, it is true for distinct buffers which hold
+    /// the same data.
+    pub fn content_eq(&self, other: &Buf<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        self.as_slice() == other.as_slice()
+    }
+
+    /// Returns the number of handles to the buffer, including this one, the
+    /// `BufMut` if it still exists, and those retained by any `Bytes`.
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.buf)
+    }
+}
+
+// Not derived, to avoid requiring `T: Clone`.
+impl<T> Clone for Buf<T> {
+    fn clone(&self) -> Self {
+        Buf {
+            buf: self.buf.clone(),
+            filled: self.filled,
+        }
+    }
+}
+
+impl<T> Debug for Buf<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Buf").finish_non_exhaustive()
     }
 }
 
-impl PartialEq for Buf {
+impl<T> PartialEq for Buf<T> {
     fn eq(&self, other: &Self) -> bool {
         Rc::ptr_eq(&self.buf, &other.buf)
     }
 }
 
-impl Eq for Buf {}
+impl<T> Eq for Buf<T> {}
+
+/// The maximum number of elements past the filled portion, which `unfilled`
+/// initializes at once, so a large buffer is not all initialized on the first
+/// read.
+pub(crate) const INITIALIZE_WINDOW: usize = 8192;
 
 /// A mutable handle into the current buffer. Only one such handle can be
 /// created, and it has exclusive mutable access for filling the buffer.
@@ -55,76 +501,425 @@ impl Eq for Buf {}
 /// |     filled     | unfilled |
 /// +----------------+----------+
 /// ```
-pub struct BufMut {
-    buf: Rc<UnsafeCell<[u8]>>,
+///
+/// The buffer is not initialized when allocated. Instead, the unfilled portion
+/// is lazily filled with default values as it is exposed, at most 8192
+/// elements at a time, and only the initialized prefix is ever observed.
+pub struct BufMut<T = u8> {
+    buf: Rc<UnsafeCell<[MaybeUninit<T>]>>,
     consumed: usize,
     filled: usize,
+    /// The length of the initialized prefix, which is at least `filled`.
+    initialized: usize,
 }
 
-impl BufMut {
+impl<T: Copy> BufMut<T> {
+    /// Allocates a buffer with the given capacity. The allocation is left
+    /// uninitialized, so this does not touch the memory. Only elements below
+    /// the initialized watermark are ever observed, so this is safe.
     pub fn new(capacity: usize) -> Self {
-        let mut buf = Rc::<[u8]>::new_uninit_slice(capacity);
-        Rc::get_mut(&mut buf).unwrap().fill(MaybeUninit::new(0));
-        let buf = unsafe { mem::transmute(buf.assume_init()) };
+        let buf = Rc::<[T]>::new_uninit_slice(capacity);
+        // SAFETY: `UnsafeCell<T>` has the same in-memory representation as `T`.
+        let buf = unsafe { Rc::from_raw(Rc::into_raw(buf) as *const UnsafeCell<[MaybeUninit<T>]>) };
         BufMut {
             buf,
             consumed: 0,
             filled: 0,
+            initialized: 0,
         }
     }
 
-    pub fn consumed(&self) -> &[u8] {
+    pub fn consumed(&self) -> &[T] {
         let buf = self.buf.get();
-        unsafe { slice::from_raw_parts(buf as *mut u8, self.consumed) }
+        unsafe { slice::from_raw_parts(buf as *mut T, self.consumed) }
     }
 
-    pub fn available(&self) -> &[u8] {
+    pub fn available(&self) -> &[T] {
         let buf = self.buf.get();
         unsafe {
-            let ptr = (buf as *mut u8).add(self.consumed);
+            let ptr = (buf as *mut T).add(self.consumed);
             slice::from_raw_parts(ptr, self.filled - self.consumed)
         }
     }
 
-    pub fn unfilled(&mut self) -> &mut [u8] {
+    /// Returns the filled portion, which is the consumed and available
+    /// elements.
+    pub fn filled(&self) -> &[T] {
+        let buf = self.buf.get();
+        unsafe { slice::from_raw_parts(buf as *mut T, self.filled) }
+    }
+
+    /// Returns the available elements mutably, so they can be edited in place
+    /// before they are consumed.
+    ///
+    /// Every `Buf` can access the elements which were filled when it was
+    /// borrowed, which may include available elements, and every `Bytes`
+    /// retains such a `Buf`. Editing them would violate persistence, so this
+    /// is only allowed when this handle uniquely owns the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is shared by any `Buf` or `Bytes`.
+    pub fn available_mut(&mut self) -> &mut [T] {
+        assert!(
+            Rc::get_mut(&mut self.buf).is_some(),
+            "cannot mutate a shared buffer",
+        );
+        // SAFETY: The buffer is uniquely owned.
+        unsafe { self.available_mut_unchecked() }
+    }
+
+    /// Returns the available elements mutably, like
+    /// [`BufMut::available_mut`], but without checking that the buffer is
+    /// uniquely owned.
+    ///
+    /// # Safety
+    ///
+    /// No `Buf` which was borrowed while any of the available elements were
+    /// filled may be alive, including those retained by `Bytes`, nor may one
+    /// be borrowed while the returned slice is alive. A `Buf` or `Bytes` that
+    /// was borrowed when the available elements were not yet filled cannot
+    /// observe them and is allowed.
+    pub unsafe fn available_mut_unchecked(&mut self) -> &mut [T] {
+        let buf = self.buf.get();
+        unsafe {
+            let ptr = (buf as *mut T).add(self.consumed);
+            slice::from_raw_parts_mut(ptr, self.filled - self.consumed)
+        }
+    }
+
+    /// Returns the initialized prefix of the unfilled portion. It extends at
+    /// most 8192 elements past the filled portion, unless more
+    /// were already initialized, so it may be shorter than the room left in
+    /// the buffer. It is only empty when the buffer is full.
+    pub fn unfilled(&mut self) -> &mut [T]
+    where
+        T: Default,
+    {
         let buf = self.buf.get();
+        let window = self.filled.saturating_add(INITIALIZE_WINDOW).min(buf.len());
+        self.initialize(window);
         unsafe {
-            let ptr = (buf as *mut u8).add(self.filled);
-            slice::from_raw_parts_mut(ptr, buf.len() - self.filled)
+            let ptr = (buf as *mut T).add(self.filled);
+            slice::from_raw_parts_mut(ptr, self.initialized.max(window) - self.filled)
         }
     }
 
-    pub fn consume(&mut self, n: usize) -> &[u8] {
-        assert!(self.consumed + n <= self.filled);
+    pub fn consume(&mut self, n: usize) -> &[T] {
+        // Checked, so a huge `n` cannot wrap around and pass.
+        assert!(
+            self.consumed
+                .checked_add(n)
+                .is_some_and(|end| end <= self.filled)
+        );
         let buf = self.buf.get();
         let consumed = unsafe {
-            let ptr = (buf as *mut u8).add(self.consumed);
+            let ptr = (buf as *mut T).add(self.consumed);
             slice::from_raw_parts(ptr, n)
         };
         self.consumed += n;
         consumed
     }
 
-    pub fn fill(&mut self, n: usize) -> &[u8] {
+    pub fn fill(&mut self, n: usize) -> &[T]
+    where
+        T: Default,
+    {
         let buf = self.buf.get();
-        assert!(self.filled + n <= buf.len());
+        assert!(
+            self.filled
+                .checked_add(n)
+                .is_some_and(|end| end <= buf.len())
+        );
+        self.initialize(self.filled + n);
         let filled = unsafe {
-            let ptr = (buf as *mut u8).add(self.filled);
+            let ptr = (buf as *mut T).add(self.filled);
             slice::from_raw_parts(ptr, n)
         };
         self.filled += n;
         filled
     }
 
-    pub fn append(&mut self, data: &[u8]) {
-        let unfilled = self.unfilled();
-        unfilled[..data.len()].copy_from_slice(data);
-        self.filled += data.len();
+    /// Returns the total number of elements the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.buf.get().len()
+    }
+
+    /// Returns a pointer to the start of the unfilled portion, which may be
+    /// uninitialized, for filling it through FFI. Up to
+    /// `capacity() - filled().len()` elements may be written through it,
+    /// after which [`BufMut::assume_filled`] marks them as filled.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        let buf = self.buf.get();
+        unsafe { (buf as *mut T).add(self.filled) }
+    }
+
+    /// Marks the first `n` elements of the unfilled portion as filled, like
+    /// [`BufMut::fill`], but without initializing them, for when they were
+    /// written through [`BufMut::as_mut_ptr`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the unfilled portion.
+    ///
+    /// # Safety
+    ///
+    /// The first `n` elements of the unfilled portion must have been
+    /// initialized.
+    pub unsafe fn assume_filled(&mut self, n: usize) {
+        assert!(
+            self.filled
+                .checked_add(n)
+                .is_some_and(|end| end <= self.capacity())
+        );
+        self.filled += n;
+        self.initialized = self.initialized.max(self.filled);
+    }
+
+    /// Appends the data to the unfilled portion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data does not fit in the unfilled portion.
+    pub fn append(&mut self, data: &[T]) {
+        let buf = self.buf.get();
+        assert!(data.len() <= buf.len() - self.filled);
+        self.append_partial(data);
+    }
+
+    /// Appends as much of the data as fits in the unfilled portion and
+    /// returns the number of elements appended.
+    pub fn append_partial(&mut self, data: &[T]) -> usize {
+        let buf = self.buf.get();
+        let len = data.len().min(buf.len() - self.filled);
+        unsafe {
+            let ptr = (buf as *mut T).add(self.filled);
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, len);
+        }
+        self.filled += len;
+        self.initialized = self.initialized.max(self.filled);
+        len
+    }
+
+    /// Appends the data, making room for it first if it does not fit in the
+    /// unfilled portion, and returns a handle to the buffer which holds it.
+    ///
+    /// Room is made like [`BufMut::reserve`], so the available elements are
+    /// carried over, but the consumed elements are not. If a new buffer was
+    /// allocated, the returned handle differs from a previously borrowed one,
+    /// which remains valid.
+    pub fn append_growing(&mut self, data: &[T]) -> Buf<T> {
+        self.reserve(data.len());
+        self.append(data);
+        BufMut::borrow(self)
+    }
+
+    /// Returns a handle to the filled portion and continues with a new, empty
+    /// buffer of the same capacity. The filled portion is not copied. Any
+    /// available elements are not carried over, so they are only accessible
+    /// through the returned handle.
+    pub fn split_off(&mut self) -> Buf<T> {
+        let capacity = self.buf.get().len();
+        mem::replace(self, BufMut::new(capacity)).borrow()
+    }
+
+    /// Rewinds the consumed portion to end at `consumed`, so the elements after
+    /// it are available again. They are filled, so they are not modified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `consumed` is past the end of the consumed portion.
+    pub fn unconsume_to(&mut self, consumed: usize) {
+        assert!(consumed <= self.consumed);
+        self.consumed = consumed;
+    }
+
+    /// Rewinds the buffer to empty, so its whole capacity can be filled again.
+    ///
+    /// This would overwrite data, which violates persistence, if any `Buf` or
+    /// `Bytes` still referenced the buffer. It is only allowed when this handle
+    /// uniquely owns the buffer, which guarantees that no such references
+    /// exist and that none can be created while the buffer is reused.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is shared by any `Buf` or `Bytes`.
+    pub fn clear(&mut self) {
+        assert!(
+            Rc::get_mut(&mut self.buf).is_some(),
+            "cannot clear a shared buffer",
+        );
+        self.consumed = 0;
+        self.filled = 0;
+    }
+
+    /// Moves the available elements to the front of the buffer, reclaiming the
+    /// consumed portion for filling, when the buffer is uniquely owned.
+    /// Returns whether it was compacted. Otherwise, outstanding `Buf` and
+    /// `Bytes` may reference the consumed portion, so it is left untouched.
+    pub fn compact(&mut self) -> bool {
+        if Rc::get_mut(&mut self.buf).is_none() {
+            return false;
+        }
+        let available = self.filled - self.consumed;
+        unsafe {
+            let ptr = self.buf.get() as *mut T;
+            ptr::copy(ptr.add(self.consumed), ptr, available);
+        }
+        self.consumed = 0;
+        self.filled = available;
+        true
     }
 
-    pub fn borrow(&self) -> Buf {
+    /// Ensures that at least `additional` elements can be filled without
+    /// another allocation. Returns whether the current allocation was reused.
+    ///
+    /// When the buffer is uniquely owned, the consumed portion is no longer
+    /// referenced and can be reclaimed by moving the available elements to the
+    /// front. Otherwise, outstanding `Buf` and `Bytes` may reference any of it,
+    /// so a new allocation is made and the available elements are copied to it,
+    /// leaving the old buffer untouched.
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        let buf = self.buf.get();
+        let capacity = buf.len();
+        if capacity - self.filled >= additional {
+            return true;
+        }
+        let available = self.filled - self.consumed;
+        if capacity - available >= additional && self.compact() {
+            return true;
+        }
+        let mut new_buf = BufMut::new((available + additional).max(capacity * 2));
+        new_buf.append(self.available());
+        *self = new_buf;
+        false
+    }
+
+    /// Fills the buffer with default values from the initialized watermark up
+    /// to `len`, so that every element before `len` is initialized. Elements
+    /// past `filled` are not shared, so they can be written.
+    fn initialize(&mut self, len: usize)
+    where
+        T: Default,
+    {
+        if len > self.initialized {
+            let buf = self.buf.get();
+            for i in self.initialized..len {
+                unsafe { (buf as *mut T).add(i).write(T::default()) };
+            }
+            self.initialized = len;
+        }
+    }
+
+    pub fn borrow(&self) -> Buf<T> {
         Buf {
             buf: self.buf.clone(),
+            filled: self.filled,
+        }
+    }
+
+    /// Consumes `n` available elements and returns a shared reference to them.
+    pub fn consume_bytes(&mut self, n: usize) -> Bytes<T> {
+        let slice = self.consume(n).into();
+        Bytes {
+            buf: BufMut::borrow(self),
+            slice,
         }
     }
 }
+
+/// Copies the data into a new buffer of exactly its length, where it is
+/// available.
+impl<T: Copy> From<&[T]> for BufMut<T> {
+    fn from(data: &[T]) -> Self {
+        let mut buf = BufMut::new(data.len());
+        buf.append(data);
+        buf
+    }
+}
+
+/// Copies the data into a new buffer of exactly its length, where it is
+/// available.
+impl<T: Copy> From<Vec<T>> for BufMut<T> {
+    fn from(data: Vec<T>) -> Self {
+        BufMut::from(data.as_slice())
+    }
+}
+
+/// Appends each slice like [`BufMut::append_growing`].
+impl<'a, T: Copy> Extend<&'a [T]> for BufMut<T> {
+    fn extend<I: IntoIterator<Item = &'a [T]>>(&mut self, iter: I) {
+        for data in iter {
+            self.reserve(data.len());
+            self.append(data);
+        }
+    }
+}
+
+/// Appends each element like [`BufMut::append_growing`].
+impl<T: Copy> Extend<T> for BufMut<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.reserve(1);
+            self.append(slice::from_ref(&value));
+        }
+    }
+}
+
+/// A pool of buffers which have been replaced, so they can be reused once
+/// nothing references them.
+///
+/// A buffer is only reused when its handle in the pool is the last one, so no
+/// `Buf` or `Bytes` can observe it being overwritten. Until then, the pool
+/// keeps it alive, so its size is limited.
+pub struct BufPool<T = u8> {
+    bufs: Vec<BufMut<T>>,
+    max_len: usize,
+}
+
+impl<T: Copy> BufPool<T> {
+    /// Constructs an empty pool, which holds at most `max_len` buffers.
+    pub fn new(max_len: usize) -> Self {
+        BufPool {
+            bufs: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// Returns an empty buffer with a capacity of at least `capacity`. A buffer
+    /// in the pool is reused, if one is large enough and no longer referenced.
+    /// Otherwise, a new buffer is allocated.
+    pub fn take(&mut self, capacity: usize) -> BufMut<T> {
+        let reusable = self
+            .bufs
+            .iter_mut()
+            .position(|buf| buf.buf.get().len() >= capacity && Rc::get_mut(&mut buf.buf).is_some());
+        match reusable {
+            Some(i) => {
+                let mut buf = self.bufs.swap_remove(i);
+                buf.clear();
+                buf
+            }
+            None => BufMut::new(capacity),
+        }
+    }
+
+    /// Adds a buffer to the pool, so it can be reused once all references to
+    /// it are dropped. It is dropped instead, if the pool is full.
+    pub fn recycle(&mut self, buf: BufMut<T>) {
+        if self.bufs.len() < self.max_len {
+            self.bufs.push(buf);
+        }
+    }
+
+    /// Returns the number of buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.bufs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bufs.is_empty()
+    }
+}
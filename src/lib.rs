@@ -1,5 +1,12 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod buffer;
+#[cfg(feature = "std")]
 pub mod pair;
+#[cfg(feature = "std")]
 pub mod reader;
-#[cfg(test)]
+pub mod sync;
+#[cfg(all(test, feature = "std"))]
 mod tests;
@@ -2,9 +2,13 @@ use std::{
     cell::UnsafeCell,
     io::{self, Read},
     mem,
+    ptr::NonNull,
 };
 
-use crate::{buffer::Buf, reader::SharedReader};
+use crate::{
+    buffer::{Buf, BufMut, Bytes},
+    reader::SharedReader,
+};
 
 /// Parsed data, paired with the buffers it was parsed from.
 pub struct BufPair<T> {
@@ -13,11 +17,41 @@ pub struct BufPair<T> {
 }
 
 impl<T> BufPair<T> {
+    /// Parses a dependent value with `make` from the reader and pairs it with
+    /// the buffers it was read from.
+    ///
+    /// The dependent cannot borrow from the builder: its type `T` is chosen
+    /// outside of the closure, so it cannot name the lifetime of the borrow,
+    /// which is introduced by the higher-ranked bound. Retain [`Bytes`] in the
+    /// dependent instead.
+    ///
+    /// ```compile_fail
+    /// use std::io;
+    ///
+    /// use self_ref_buffer::{pair::BufPair, reader::SharedReader};
+    ///
+    /// struct Ident<'a> {
+    ///     author: &'a [u8],
+    ///     committer: &'a [u8],
+    /// }
+    ///
+    /// let s = "author: Author\ncommitter: Committer\n";
+    /// let mut r = SharedReader::new(s.as_bytes(), 100);
+    /// let ident = BufPair::new(&mut r, |builder| -> io::Result<_> {
+    ///     let author = builder.read_line_trimmed()?.strip_prefix(b"author: ");
+    ///     let committer = builder.read_line_trimmed()?.strip_prefix(b"committer: ");
+    ///     let (Some(author), Some(committer)) = (author, committer) else {
+    ///         return Err(io::Error::new(io::ErrorKind::InvalidData, "expected ident"));
+    ///     };
+    ///     // error: lifetime may not live long enough
+    ///     Ok(Ident { author, committer })
+    /// });
+    /// ```
     pub fn new<R: Read, F, E>(reader: &mut SharedReader<R>, make: F) -> Result<Self, E>
     where
         F: for<'a> FnOnce(&'a BufBuilder<'_, R>) -> Result<T, E>,
     {
-        let builder = BufBuilder::new(reader);
+        let builder = BufBuilder::new(reader, Vec::new());
         let dependent = make(&builder)?;
         Ok(BufPair {
             dependent,
@@ -25,6 +59,60 @@ impl<T> BufPair<T> {
         })
     }
 
+    /// Transforms the dependent value, while retaining the same buffers.
+    pub fn map<U, F>(self, f: F) -> BufPair<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        BufPair {
+            dependent: f(self.dependent),
+            owner: self.owner,
+        }
+    }
+
+    /// Fallibly transforms the dependent value, while retaining the same
+    /// buffers. On error, the buffers are dropped.
+    pub fn try_map<U, E, F>(self, f: F) -> Result<BufPair<U>, E>
+    where
+        F: for<'a> FnOnce(&'a T) -> Result<U, E>,
+    {
+        let dependent = f(&self.dependent)?;
+        Ok(BufPair {
+            dependent,
+            owner: self.owner,
+        })
+    }
+
+    /// Continues parsing from the reader, with a dependent value which depends
+    /// on the current one. The buffers it is parsed from are added to the
+    /// current buffers. On error, all of the buffers are dropped.
+    pub fn and_then<R: Read, U, E, F>(
+        self,
+        reader: &mut SharedReader<R>,
+        f: F,
+    ) -> Result<BufPair<U>, E>
+    where
+        F: for<'a> FnOnce(T, &'a BufBuilder<'_, R>) -> Result<U, E>,
+    {
+        // Start from the current buffers, so a buffer shared by both parses is
+        // not duplicated.
+        let builder = BufBuilder::new(reader, self.owner);
+        let dependent = f(self.dependent, &builder)?;
+        Ok(BufPair {
+            dependent,
+            owner: builder.bufs.into_inner(),
+        })
+    }
+
+    /// Borrows the dependent value, with new handles to the same buffers, so
+    /// the pair can be passed by value without giving up the original.
+    pub fn as_pair(&self) -> BufPair<&T> {
+        BufPair {
+            dependent: &self.dependent,
+            owner: self.owner.clone(),
+        }
+    }
+
     pub fn dependent(&self) -> &T {
         &self.dependent
     }
@@ -32,6 +120,84 @@ impl<T> BufPair<T> {
     pub fn owner(&self) -> &[Buf] {
         &self.owner
     }
+
+    /// Drops the buffers and returns the dependent value. It cannot borrow
+    /// from the buffers, since it is `'static`, though it may still retain
+    /// some through `Bytes`.
+    pub fn into_dependent(self) -> T
+    where
+        T: 'static,
+    {
+        self.dependent
+    }
+}
+
+/// Iterates over the dependent value, when it is a collection.
+impl<'a, T> IntoIterator for &'a BufPair<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        (&self.dependent).into_iter()
+    }
+}
+
+/// Serializes only the dependent value. The buffers are not needed to
+/// reconstruct it, since it is serialized by value.
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for BufPair<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.dependent.serialize(serializer)
+    }
+}
+
+impl<T: Relocate> BufPair<T> {
+    /// Copies every slice retained by the dependent into a single new buffer,
+    /// which exactly fits them, and replaces the owning buffers with it. This
+    /// releases the old buffers, when only small slices of them are retained.
+    pub fn compact(&mut self) {
+        let mut len = 0;
+        self.dependent.relocate(&mut |bytes| len += bytes.len());
+        let mut buf = BufMut::new(len);
+        self.dependent.relocate(&mut |bytes| {
+            buf.append(bytes.slice());
+            *bytes = buf.consume_bytes(bytes.len());
+        });
+        self.owner = vec![buf.borrow()];
+    }
+}
+
+/// A dependent value, which can be moved to new buffers by
+/// [`BufPair::compact`].
+pub trait Relocate {
+    /// Calls `f` with every `Bytes` retained by this value, in a consistent
+    /// order. `f` may replace each with a `Bytes` of the same contents.
+    fn relocate(&mut self, f: &mut dyn FnMut(&mut Bytes));
+}
+
+impl Relocate for Bytes {
+    fn relocate(&mut self, f: &mut dyn FnMut(&mut Bytes)) {
+        f(self);
+    }
+}
+
+impl<T: Relocate> Relocate for Option<T> {
+    fn relocate(&mut self, f: &mut dyn FnMut(&mut Bytes)) {
+        if let Some(value) = self {
+            value.relocate(f);
+        }
+    }
+}
+
+impl<T: Relocate> Relocate for Vec<T> {
+    fn relocate(&mut self, f: &mut dyn FnMut(&mut Bytes)) {
+        for value in self {
+            value.relocate(f);
+        }
+    }
 }
 
 pub struct BufBuilder<'r, R> {
@@ -40,27 +206,73 @@ pub struct BufBuilder<'r, R> {
 }
 
 impl<'r, R: Read> BufBuilder<'r, R> {
-    fn new(reader: &'r mut SharedReader<R>) -> Self {
+    fn new(reader: &'r mut SharedReader<R>, bufs: Vec<Buf>) -> Self {
         BufBuilder {
             reader: UnsafeCell::new(reader),
-            bufs: UnsafeCell::new(Vec::new()),
+            bufs: UnsafeCell::new(bufs),
         }
     }
 
     /// Reads a line from the reader and stores the buffer it came from, so
+    /// the line lives as long as the builder.
     pub fn read_line(&self) -> io::Result<&[u8]> {
-        let reader = unsafe { &mut *self.reader.get() };
+        let reader = unsafe { &mut **self.reader.get() };
         let line = reader.read_line()?;
+        Ok(self.track(line))
+    }
+
+    /// Reads until the delimiter from the reader and stores the buffer it came
+    /// from, so the data lives as long as the builder.
+    pub fn read_until(&self, delim: u8) -> io::Result<&[u8]> {
+        let reader = unsafe { &mut **self.reader.get() };
+        let bytes = reader.read_until(delim)?;
+        Ok(self.track(bytes))
+    }
+
+    /// Reads exactly `n` bytes from the reader and stores the buffer they came
+    /// from, so the data lives as long as the builder.
+    pub fn read_exact(&self, n: usize) -> io::Result<&[u8]> {
+        let reader = unsafe { &mut **self.reader.get() };
+        let bytes = reader.read_exact(n)?;
+        Ok(self.track(bytes))
+    }
+
+    /// Reads a line like [`BufBuilder::read_line`], but excludes the line
+    /// ending, as trimmed by [`LineEnding::trim`](crate::reader::LineEnding::trim).
+    pub fn read_line_trimmed(&self) -> io::Result<&[u8]> {
+        let line_ending = unsafe { &**self.reader.get() }.line_ending();
+        let line = self.read_line()?;
+        Ok(line_ending.trim(line))
+    }
+
+    /// Reads a line from the reader and stores the buffer it came from, like
+    /// [`BufBuilder::read_line`], but returns the line as `Bytes`, which owns
+    /// a handle to its buffer. The dependent can then hold it directly,
+    /// without relying on the lifetime of the builder.
+    pub fn read_line_bytes(&self) -> io::Result<Bytes> {
+        let reader = unsafe { &mut **self.reader.get() };
+        let line = reader.read_line()?;
+        self.store(&line.buf);
+        Ok(line)
+    }
+
+    /// Stores the buffer of the bytes, if it is not already the latest, and
+    /// extends the lifetime of the slice to that of the builder.
+    fn track(&self, bytes: Bytes) -> &[u8] {
+        self.store(&bytes.buf);
+        // Transmute the lifetime from 'r to 'self.
+        // SAFETY: We are tracking the buffer, so the borrow now can live as
+        // long as the chain.
+        unsafe { mem::transmute::<NonNull<[u8]>, &[u8]>(bytes.slice) }
+    }
+
+    /// Stores the buffer, if it is not already the latest.
+    fn store(&self, buf: &Buf) {
         // SAFETY: The vec only grows and any returned slices are not
         // invalidated if their `Buf` moves, since the buffers are boxed.
         let bufs = unsafe { &mut *self.bufs.get() };
-        if !bufs.last().is_some_and(|last| last == &line.buf) {
-            bufs.push(line.buf);
+        if !bufs.last().is_some_and(|last| last == buf) {
+            bufs.push(buf.clone());
         }
-        // Transmute the lifetime from 'r to 'self.
-        // SAFETY: We are tracking the buffer, so the borrow now can live as
-        // long as the chain.
-        let slice = unsafe { mem::transmute(line.slice) };
-        Ok(slice)
     }
 }
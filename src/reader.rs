@@ -1,6 +1,16 @@
-use std::io::{self, Read};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, Read},
+    iter::FusedIterator,
+    mem, str,
+};
 
-use crate::buffer::{Buf, BufMut, Bytes};
+use crate::{
+    buffer::{Buf, BufMut, BufPool, Bytes},
+    pair::BufPair,
+};
 
 /// A reader which does not overwrite its buffers, so slices can be freely
 /// retained while reading.
@@ -9,55 +19,1073 @@ pub struct SharedReader<R> {
     buf: BufMut,
     /// The initial capacity for a new buffer.
     initial_capacity: usize,
+    /// How to size a new buffer, when the current one is too small.
+    growth: GrowthStrategy,
+    /// The maximum length of a delimited read, including the delimiter.
+    max_line: Option<usize>,
+    /// The maximum number of buffers a delimited read may replace the current
+    /// buffer with.
+    max_buffers: Option<usize>,
+    /// The number of bytes consumed from the stream, other than from the
+    /// current buffer.
+    base_offset: usize,
+    /// Where new buffers are taken from and replaced buffers are returned to,
+    /// if any.
+    pool: Option<BufPool>,
+    /// Which sequences terminate a line.
+    line_ending: LineEnding,
+    /// Whether the last line, or any read since, had to be copied to a new
+    /// buffer.
+    last_line_copied: bool,
+    /// The position and length of the line found by the last
+    /// [`SharedReader::peek_line`], so it is not searched for or read again.
+    peeked_line: Option<(usize, usize)>,
+    /// Allocates new buffers, when there is no pool, if set.
+    make_buf: Option<Box<dyn FnMut(usize) -> BufMut>>,
+    /// The number of bytes read from the underlying reader.
+    bytes_read: u64,
+}
+
+/// The strategy for computing the capacity of a new buffer, when the data
+/// being read does not fit in the current buffer. The new capacity is always
+/// larger than the partial data carried over from the old buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GrowthStrategy {
+    /// Double the length of the partial data, but use at least the initial
+    /// capacity.
+    #[default]
+    Double,
+    /// Use a fixed capacity. When the partial data does not fit in it, add
+    /// that amount to the length of the partial data instead, so the new
+    /// buffer still has room for more than one byte.
+    Fixed(usize),
+    /// Add a fixed amount, which is at least 1, to the length of the partial
+    /// data.
+    Additive(usize),
+}
+
+impl GrowthStrategy {
+    /// Returns the capacity of a new buffer, which the partial data is carried
+    /// over to. It saturates, instead of overflowing, for huge lengths.
+    ///
+    /// The partial data is in an allocation, so its length is at most
+    /// `isize::MAX` and there is always room for at least one more byte.
+    pub fn capacity(self, partial_len: usize, initial_capacity: usize) -> usize {
+        let capacity = match self {
+            GrowthStrategy::Double => partial_len.saturating_mul(2).max(initial_capacity),
+            GrowthStrategy::Fixed(capacity) if capacity <= partial_len => {
+                partial_len.saturating_add(capacity.max(1))
+            }
+            GrowthStrategy::Fixed(capacity) => capacity,
+            GrowthStrategy::Additive(additional) => partial_len.saturating_add(additional.max(1)),
+        };
+        capacity.max(partial_len.saturating_add(1))
+    }
+}
+
+/// The sequences which terminate a line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// LF.
+    #[default]
+    Lf,
+    /// CRLF. A bare CR or LF does not terminate a line.
+    CrLf,
+    /// CR, as in classic Mac OS.
+    Cr,
+    /// CRLF, LF, or CR. A CR immediately followed by LF terminates one line.
+    Any,
+}
+
+impl LineEnding {
+    /// Returns the length of the line, including the line ending, if a line
+    /// ending is found in `buf` after the prefix of length `searched`, which
+    /// has already been searched.
+    fn find(self, buf: &[u8], searched: usize) -> Option<usize> {
+        let find_byte = |b: u8| {
+            let i = buf[searched..].iter().position(|&c| c == b)?;
+            Some(searched + i + 1)
+        };
+        match self {
+            LineEnding::Lf => find_byte(b'\n'),
+            LineEnding::Cr => find_byte(b'\r'),
+            LineEnding::CrLf => {
+                // Back up, in case the CRLF straddles the previous search.
+                let start = searched.saturating_sub(1);
+                let i = buf[start..].windows(2).position(|w| w == b"\r\n")?;
+                Some(start + i + 2)
+            }
+            LineEnding::Any => {
+                // Back up, in case a CR ended the previous search, since it
+                // may be followed by LF.
+                let start = searched.saturating_sub(1);
+                let i = start
+                    + buf[start..]
+                        .iter()
+                        .position(|&b| b == b'\r' || b == b'\n')?;
+                match (buf[i], buf.get(i + 1)) {
+                    (b'\r', Some(b'\n')) => Some(i + 2),
+                    // Wait for the next byte, unless EOF is reached.
+                    (b'\r', None) => None,
+                    _ => Some(i + 1),
+                }
+            }
+        }
+    }
+
+    /// Removes the line ending from the end of the line, if it has one. For
+    /// `Lf`, a CR before the LF is also removed.
+    pub fn trim(self, line: &[u8]) -> &[u8] {
+        match self {
+            LineEnding::Lf => match line.strip_suffix(b"\n") {
+                Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+                None => line,
+            },
+            LineEnding::CrLf => line.strip_suffix(b"\r\n").unwrap_or(line),
+            LineEnding::Cr => line.strip_suffix(b"\r").unwrap_or(line),
+            LineEnding::Any => {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                line.strip_suffix(b"\r").unwrap_or(line)
+            }
+        }
+    }
 }
 
 impl<R: Read> SharedReader<R> {
     pub fn new(reader: R, initial_capacity: usize) -> Self {
+        SharedReader::with_growth(reader, initial_capacity, GrowthStrategy::Double)
+    }
+
+    pub fn with_growth(reader: R, initial_capacity: usize, growth: GrowthStrategy) -> Self {
+        SharedReader {
+            growth,
+            ..SharedReader::from_buf(reader, BufMut::new(initial_capacity), initial_capacity)
+        }
+    }
+
+    /// Constructs a reader, which starts with an existing buffer. Any data
+    /// which is filled, but not consumed, is read before the underlying
+    /// reader. The initial capacity for new buffers is that of the given
+    /// buffer, and the position starts at its consumed length.
+    pub fn with_buffer(reader: R, buf: BufMut) -> Self {
+        let initial_capacity = buf.capacity();
+        SharedReader::from_buf(reader, buf, initial_capacity)
+    }
+
+    /// Constructs a reader, which reuses buffers from the pool, once they are
+    /// no longer referenced, instead of always allocating new ones.
+    pub fn with_pool(reader: R, initial_capacity: usize, mut pool: BufPool) -> Self {
+        let buf = pool.take(initial_capacity);
+        SharedReader {
+            pool: Some(pool),
+            ..SharedReader::from_buf(reader, buf, initial_capacity)
+        }
+    }
+
+    /// Constructs a reader, which allocates its buffers with `make_buf`,
+    /// instead of [`BufMut::new`]. It is called with the capacity, which the
+    /// returned buffer must have at least, and the buffer must be empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `make_buf` returns a buffer which is not empty or has less
+    /// than the requested capacity.
+    pub fn with_allocator<F>(reader: R, initial_capacity: usize, mut make_buf: F) -> Self
+    where
+        F: FnMut(usize) -> BufMut + 'static,
+    {
+        let buf = make_buf(initial_capacity);
+        assert_allocated(&buf, initial_capacity);
+        SharedReader {
+            make_buf: Some(Box::new(make_buf)),
+            ..SharedReader::from_buf(reader, buf, initial_capacity)
+        }
+    }
+
+    /// Constructs a reader with the default configuration, which starts with
+    /// the given buffer.
+    fn from_buf(reader: R, buf: BufMut, initial_capacity: usize) -> Self {
         SharedReader {
             reader,
-            buf: BufMut::new(initial_capacity),
+            buf,
             initial_capacity,
+            growth: GrowthStrategy::Double,
+            max_line: None,
+            max_buffers: None,
+            base_offset: 0,
+            pool: None,
+            line_ending: LineEnding::Lf,
+            last_line_copied: false,
+            peeked_line: None,
+            make_buf: None,
+            bytes_read: 0,
+        }
+    }
+
+    /// Constructs a reader, which terminates lines with the given line ending,
+    /// instead of LF.
+    pub fn with_line_ending(reader: R, initial_capacity: usize, line_ending: LineEnding) -> Self {
+        SharedReader {
+            line_ending,
+            ..SharedReader::new(reader, initial_capacity)
+        }
+    }
+
+    /// Constructs a reader, which limits the length of lines and other
+    /// delimited reads to `max` bytes, including the delimiter. Reading a
+    /// longer line is an error of kind [`io::ErrorKind::InvalidData`]. The rest
+    /// of the line is then discarded, through its delimiter or EOF, so the next
+    /// read starts after it. If reading fails while discarding, that error is
+    /// returned instead.
+    pub fn with_max_line(reader: R, initial_capacity: usize, max: usize) -> Self {
+        SharedReader {
+            max_line: Some(max),
+            ..SharedReader::new(reader, initial_capacity)
         }
     }
 
-    /// Reads a line until LF or EOF. Returns a shared reference to a slice in
-    /// the current buffer which contains the line.
+    /// Constructs a reader, which limits the number of times a line or other
+    /// delimited read may replace the current buffer with a new one to `max`.
+    /// This bounds the growth for a single read, even when the length is not
+    /// limited. Exceeding it is an error of kind [`io::ErrorKind::InvalidData`],
+    /// and the line is then discarded, like with [`SharedReader::with_max_line`].
+    pub fn with_max_buffers(reader: R, initial_capacity: usize, max: usize) -> Self {
+        SharedReader {
+            max_buffers: Some(max),
+            ..SharedReader::new(reader, initial_capacity)
+        }
+    }
+
+    /// Reads a line until the line ending or EOF. Returns a shared reference to
+    /// a slice in the current buffer which contains the line, including the
+    /// line ending. The line ending is LF, unless configured with
+    /// [`SharedReader::with_line_ending`].
     pub fn read_line(&mut self) -> io::Result<Bytes> {
-        fn find_lf(buf: &[u8]) -> Option<usize> {
-            buf.iter().position(|&b| b == b'\n')
+        // Keep whether the peek copied the line.
+        if let Some(len) = self.peeked_line_len() {
+            self.peeked_line = None;
+            return Ok(self.consume(len));
         }
+        let line_ending = self.line_ending;
+        // Set by `grow`, when it copies the start of the line.
+        self.last_line_copied = false;
+        self.read_until_end(Some(1), |buf, searched| line_ending.find(buf, searched))
+    }
 
-        let len = if let Some(i) = find_lf(self.buf.available()) {
-            i + 1
-        } else {
-            let mut len = self.buf.available().len();
-            loop {
-                if self.buf.unfilled().is_empty() {
-                    let partial = self.buf.available();
-                    let mut new_buf = BufMut::new((partial.len() * 2).max(self.initial_capacity));
-                    new_buf.append(partial);
-                    self.buf = new_buf;
+    /// Returns whether the line returned by the last [`SharedReader::read_line`]
+    /// did not fit in the buffer it started in, so part of it was copied to a
+    /// new buffer. If this is frequent, the initial capacity may be too small
+    /// for the lines. Other reads since then, which copy their partial data
+    /// the same way, also set it.
+    pub fn last_line_copied(&self) -> bool {
+        self.last_line_copied
+    }
+
+    /// Returns the next line until the line ending or EOF without consuming
+    /// it, reading more if necessary. A following [`SharedReader::read_line`]
+    /// or `peek_line` returns the same line, without searching for it again or
+    /// reading from the underlying reader, even when it ended at EOF, and
+    /// leaves any lookahead after it buffered.
+    pub fn peek_line(&mut self) -> io::Result<&[u8]> {
+        let len = match self.peeked_line_len() {
+            Some(len) => len,
+            None => {
+                let line_ending = self.line_ending;
+                self.last_line_copied = false;
+                let len =
+                    self.fill_until_end(Some(1), |buf, searched| line_ending.find(buf, searched))?;
+                self.peeked_line = Some((self.position(), len));
+                len
+            }
+        };
+        Ok(&self.buf.available()[..len])
+    }
+
+    /// Returns the length of the line found by the last
+    /// [`SharedReader::peek_line`], if it is still next.
+    fn peeked_line_len(&self) -> Option<usize> {
+        let (position, len) = self.peeked_line?;
+        (position == self.position() && len <= self.buf.available().len()).then_some(len)
+    }
+
+    /// Returns which sequences terminate a line.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Reads a line until the line ending or EOF, like
+    /// [`SharedReader::read_line`], and appends it to `out`, for when the line
+    /// does not need to be retained. Returns the number of bytes appended,
+    /// which is 0 at EOF.
+    pub fn read_line_into(&mut self, out: &mut Vec<u8>) -> io::Result<usize> {
+        let line = self.read_line()?;
+        out.extend_from_slice(&line);
+        Ok(line.len())
+    }
+
+    /// Reads a line until the line ending or EOF, like
+    /// [`SharedReader::read_line`]. Returns a shared reference to the line and
+    /// the line as a string, which borrows it when it is valid UTF-8 and
+    /// otherwise replaces invalid sequences with U+FFFD.
+    pub fn read_line_str(&mut self) -> io::Result<(Bytes, Cow<'_, str>)> {
+        let line = self.read_line()?;
+        // The line was just consumed from the current buffer, so it is at the
+        // end of the consumed portion and can be borrowed from there.
+        let consumed = self.buf.consumed();
+        let s = String::from_utf8_lossy(&consumed[consumed.len() - line.len()..]);
+        Ok((line, s))
+    }
+
+    /// Reads a line until the line ending or EOF. Returns the line as a string,
+    /// which is borrowed from the buffer, if it is valid UTF-8, and otherwise
+    /// returns a shared reference to the bytes of the line.
+    pub fn read_line_utf8(&mut self) -> io::Result<Result<&str, Bytes>> {
+        let line = self.read_line()?;
+        // Borrow the line from the consumed portion, like `read_line_str`.
+        let consumed = self.buf.consumed();
+        match str::from_utf8(&consumed[consumed.len() - line.len()..]) {
+            Ok(s) => Ok(Ok(s)),
+            Err(_) => Ok(Err(line)),
+        }
+    }
+
+    /// Reads a line until CRLF, LF, or EOF. Returns a shared reference to a
+    /// slice in the current buffer which contains the line, excluding the line
+    /// terminator. It always splits on LF, regardless of the configured line
+    /// ending.
+    pub fn read_line_crlf(&mut self) -> io::Result<Bytes> {
+        let line = self.read_until(b'\n')?;
+        Ok(match line.strip_suffix(b"\n") {
+            Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+            None => line,
+        })
+    }
+
+    /// Reads until the delimiter or EOF. Returns a shared reference to a slice
+    /// in the current buffer which contains the data, including the delimiter,
+    /// if found.
+    pub fn read_until(&mut self, delim: u8) -> io::Result<Bytes> {
+        self.read_until_by(|b| b == delim)
+    }
+
+    /// Reads until the delimiter or EOF, like [`SharedReader::read_until`],
+    /// which includes the delimiter.
+    pub fn read_until_inclusive(&mut self, delim: u8) -> io::Result<Bytes> {
+        self.read_until(delim)
+    }
+
+    /// Reads until the delimiter or EOF. Returns a shared reference to a slice
+    /// in the current buffer which contains the data, excluding the delimiter,
+    /// though the delimiter is consumed.
+    pub fn read_until_exclusive(&mut self, delim: u8) -> io::Result<Bytes> {
+        let bytes = self.read_until(delim)?;
+        Ok(bytes.strip_suffix(&[delim]).unwrap_or(bytes))
+    }
+
+    /// Reads until any of the delimiters or EOF. Returns a shared reference to
+    /// a slice in the current buffer which contains the data, including the
+    /// delimiter, if found, and the delimiter which matched, or `None` at EOF.
+    pub fn read_until_any(&mut self, delims: &[u8]) -> io::Result<(Bytes, Option<u8>)> {
+        let bytes = self.read_until_by(|b| delims.contains(&b))?;
+        let delim = bytes.last().copied().filter(|b| delims.contains(b));
+        Ok((bytes, delim))
+    }
+
+    /// Reads until a delimiting sequence or EOF. Returns a shared reference to
+    /// a slice in the current buffer which contains the data, including the
+    /// delimiter, if found. An empty delimiter matches immediately.
+    pub fn read_until_seq(&mut self, delim: &[u8]) -> io::Result<Bytes> {
+        if delim.is_empty() {
+            return Ok(self.consume(0));
+        }
+        self.read_until_end(Some(delim.len() - 1), |buf, searched| {
+            // Back up, in case the delimiter straddles the previous search.
+            let start = searched.saturating_sub(delim.len() - 1);
+            let i = buf[start..].windows(delim.len()).position(|w| w == delim)?;
+            Some(start + i + delim.len())
+        })
+    }
+
+    /// Reads until `pred` locates the end of the data or EOF. Returns a shared
+    /// reference to a slice in the current buffer which contains the data.
+    ///
+    /// `pred` is called with all of the available bytes and returns the length
+    /// of the data to stop, or `None` to read more. It is called again after
+    /// each read with a longer slice, which starts at the same byte, even when
+    /// the data is moved to a new buffer. At EOF, all of the available bytes
+    /// are returned. Like the other delimited reads, the data is limited by the
+    /// maximum line length. When it exceeds a limit, only the buffered bytes
+    /// are discarded, since its end cannot be found without them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pred` returns a length beyond the end of the slice.
+    pub fn read_while<F>(&mut self, mut pred: F) -> io::Result<Bytes>
+    where
+        F: FnMut(&[u8]) -> Option<usize>,
+    {
+        self.read_until_end(None, |buf, _| pred(buf))
+    }
+
+    /// Reads until a byte matching the predicate or EOF.
+    fn read_until_by<F: FnMut(u8) -> bool>(&mut self, mut is_delim: F) -> io::Result<Bytes> {
+        self.read_until_end(Some(0), |buf, searched| {
+            let i = buf[searched..].iter().position(|&b| is_delim(b))?;
+            Some(searched + i + 1)
+        })
+    }
+
+    /// Reads until `find` locates the end of the data or EOF. `find` is called
+    /// with the available bytes and the length of the prefix of them, which
+    /// has already been searched, and returns the length of the data.
+    /// `overlap` is the number of searched bytes at the end, which `find` may
+    /// search again, or `None` if it needs all of the data.
+    ///
+    /// The search stops at the first match and the data through it is
+    /// consumed, so the next read starts searching where this one stopped.
+    /// With a single-byte delimiter, as for `read_until` and LF lines, no byte
+    /// is searched twice by successive reads, even when the whole input is
+    /// buffered. Otherwise, up to `overlap` bytes are searched again after each
+    /// read, or all of them for `read_while`.
+    fn read_until_end<F>(&mut self, overlap: Option<usize>, find: F) -> io::Result<Bytes>
+    where
+        F: FnMut(&[u8], usize) -> Option<usize>,
+    {
+        let len = self.fill_until_end(overlap, find)?;
+        Ok(self.consume(len))
+    }
+
+    /// Reads like [`SharedReader::read_until_end`], but leaves the data
+    /// available and returns its length.
+    ///
+    /// When the data exceeds a limit, it is discarded through the end or EOF,
+    /// so the next read starts after it, and the error is returned.
+    fn fill_until_end<F>(&mut self, overlap: Option<usize>, mut find: F) -> io::Result<usize>
+    where
+        F: FnMut(&[u8], usize) -> Option<usize>,
+    {
+        let max_line = self.max_line.unwrap_or(usize::MAX);
+        let max_buffers = self.max_buffers.unwrap_or(usize::MAX);
+        let mut searched = 0;
+        let mut buffers = 0;
+        let len = loop {
+            let available = self.buf.available();
+            if let Some(len) = find(available, searched) {
+                break len;
+            }
+            searched = available.len();
+            if searched > max_line {
+                self.discard_until_end(overlap, &mut find, searched)?;
+                return Err(line_too_long(max_line));
+            }
+            if self.buf.unfilled().is_empty() {
+                if buffers == max_buffers {
+                    self.discard_until_end(overlap, &mut find, searched)?;
+                    return Err(ReaderError::TooManyBuffers { limit: max_buffers }.into());
                 }
-                let n = self.reader.read(self.buf.unfilled())?;
-                if n == 0 {
-                    break;
+                buffers += 1;
+                // Only allocate enough to tell whether the line is too long.
+                self.grow(0, max_line.saturating_add(1));
+            }
+            if self.fill_unfilled()? == 0 {
+                break searched;
+            }
+        };
+        if len > max_line {
+            self.buf.consume(len);
+            return Err(line_too_long(max_line));
+        }
+        Ok(len)
+    }
+
+    /// Consumes the data through the end located by `find` or EOF, without
+    /// retaining it, after it exceeded a limit. Only the last `overlap` bytes
+    /// are kept while searching, so it needs no more room than a small buffer.
+    /// When `overlap` is `None`, only the available bytes are discarded.
+    fn discard_until_end<F>(
+        &mut self,
+        overlap: Option<usize>,
+        find: &mut F,
+        mut searched: usize,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&[u8], usize) -> Option<usize>,
+    {
+        let Some(overlap) = overlap else {
+            self.buf.consume(self.buf.available().len());
+            return Ok(());
+        };
+        loop {
+            let available = self.buf.available();
+            if let Some(len) = find(available, searched) {
+                self.buf.consume(len);
+                return Ok(());
+            }
+            let keep = overlap.min(available.len());
+            self.buf.consume(available.len() - keep);
+            searched = keep;
+            if self.buf.unfilled().is_empty() {
+                self.grow(0, usize::MAX);
+            }
+            if self.fill_unfilled()? == 0 {
+                self.buf.consume(self.buf.available().len());
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads exactly `n` bytes. Returns a shared reference to a slice in the
+    /// current buffer which contains the data.
+    ///
+    /// If EOF is reached before `n` bytes are read, an error of kind
+    /// [`io::ErrorKind::UnexpectedEof`] is returned and the partial data
+    /// remains buffered.
+    pub fn read_exact(&mut self, n: usize) -> io::Result<Bytes> {
+        let available = self.fill_to(n)?;
+        if available < n {
+            return Err(ReaderError::UnexpectedEof {
+                expected: n,
+                available,
+            }
+            .into());
+        }
+        Ok(self.consume(n))
+    }
+
+    /// Reads a frame, which is prefixed by its length as an unsigned LEB128
+    /// varint. Returns a shared reference to a slice in the current buffer
+    /// which contains the frame, excluding the length.
+    ///
+    /// A varint which overflows `usize` or has redundant trailing zero groups
+    /// is an error of kind [`io::ErrorKind::InvalidData`]. If EOF is reached
+    /// within the varint, the partial varint remains buffered. If EOF is
+    /// reached within the frame, the varint is consumed and the partial frame
+    /// remains buffered, like [`SharedReader::read_exact`]. The buffer grows as
+    /// the frame arrives, so a huge length on a short stream is an EOF error,
+    /// instead of an allocation of that length.
+    pub fn read_varint_frame(&mut self) -> io::Result<Bytes> {
+        let mut len = 0usize;
+        let mut i = 0;
+        loop {
+            if self.fill_to(i + 1)? <= i {
+                return Err(ReaderError::UnexpectedEof {
+                    expected: i + 1,
+                    available: i,
                 }
-                if let Some(i) = find_lf(self.buf.fill(n)) {
-                    len += i + 1;
-                    break;
+                .into());
+            }
+            let b = self.buf.available()[i];
+            let shift = 7 * i as u32;
+            let bits = (b & 0x7f) as usize;
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return Err(ReaderError::InvalidVarint.into());
+            }
+            len |= bits << shift;
+            i += 1;
+            if b & 0x80 == 0 {
+                if b == 0 && i > 1 {
+                    return Err(ReaderError::InvalidVarint.into());
                 }
-                len += n;
+                break;
             }
-            len
+        }
+        self.buf.consume(i);
+        self.read_exact(len)
+    }
+
+    /// Reads a line like [`SharedReader::read_line`], but returns a
+    /// [`ReaderError`], which can be matched on.
+    pub fn try_read_line(&mut self) -> Result<Bytes, ReaderError> {
+        Ok(self.read_line()?)
+    }
+
+    /// Reads exactly `n` bytes like [`SharedReader::read_exact`], but returns a
+    /// [`ReaderError`], which can be matched on.
+    pub fn try_read_exact(&mut self, n: usize) -> Result<Bytes, ReaderError> {
+        Ok(self.read_exact(n)?)
+    }
+
+    /// Returns the next `n` bytes without consuming them, reading more if
+    /// necessary. Fewer bytes are returned, if EOF is reached first.
+    pub fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        let len = self.fill_to(n)?.min(n);
+        Ok(&self.buf.available()[..len])
+    }
+
+    /// Reads whatever is available without waiting for a delimiter. Returns the
+    /// buffered bytes, if there are any. Otherwise, it reads from the
+    /// underlying reader once and returns the newly filled bytes, which is
+    /// empty at EOF.
+    ///
+    /// Nothing is carried over between chunks, so data is never copied to a
+    /// larger buffer. A new buffer is only started when the current one is
+    /// full.
+    pub fn read_chunk(&mut self) -> io::Result<Bytes> {
+        let len = self.fill_buf()?.len();
+        Ok(self.consume(len))
+    }
+
+    /// Consumes and returns the buffered bytes, without reading from the
+    /// underlying reader, so it never blocks. It is empty when nothing is
+    /// buffered.
+    pub fn take_available(&mut self) -> Bytes {
+        let len = self.buf.available().len();
+        self.consume(len)
+    }
+
+    /// Advances past the next `n` bytes without retaining them and returns the
+    /// number of bytes skipped, which is less than `n` if EOF is reached first.
+    ///
+    /// Buffered bytes are consumed in place. The rest are read from the
+    /// underlying reader into a scratch area and dropped, so the buffer does
+    /// not grow.
+    pub fn skip(&mut self, n: usize) -> io::Result<usize> {
+        let buffered = self.buf.available().len().min(n);
+        self.buf.consume(buffered);
+        if buffered == n {
+            return Ok(n);
+        }
+        let rest = (n - buffered) as u64;
+        let skipped = io::copy(&mut (&mut self.reader).take(rest), &mut io::sink())?;
+        self.bytes_read += skipped;
+        let skipped = skipped as usize;
+        self.base_offset += skipped;
+        Ok(buffered + skipped)
+    }
+
+    /// Reads until EOF. Returns a shared reference to a slice in the current
+    /// buffer which contains all of the remaining data.
+    pub fn read_to_end(&mut self) -> io::Result<Bytes> {
+        loop {
+            if self.buf.unfilled().is_empty() {
+                self.grow(0, usize::MAX);
+            }
+            if self.fill_unfilled()? == 0 {
+                break;
+            }
+        }
+        let len = self.buf.available().len();
+        Ok(self.consume(len))
+    }
+
+    /// Reads until at least `n` bytes are available or EOF is reached and
+    /// returns the number of available bytes, without consuming any. The
+    /// available bytes are moved to a new buffer, when the current one is full.
+    ///
+    /// This is how [`SharedReader::read_exact`], [`SharedReader::peek`], and
+    /// [`SharedReader::read_varint_frame`] buffer data. The buffer grows by the
+    /// growth strategy as data arrives, instead of allocating room for all `n`
+    /// bytes first, so a huge `n` on a short stream does not allocate it.
+    pub fn fill_to(&mut self, n: usize) -> io::Result<usize> {
+        while self.buf.available().len() < n {
+            if self.buf.unfilled().is_empty() {
+                self.grow(0, usize::MAX);
+            }
+            if self.fill_unfilled()? == 0 {
+                break;
+            }
+        }
+        Ok(self.buf.available().len())
+    }
+
+    /// Reads once into the unfilled portion and returns the number of bytes
+    /// filled, retrying when interrupted. Bytes which were filled by earlier
+    /// reads remain buffered when it fails, so a later read resumes with them.
+    fn fill_unfilled(&mut self) -> io::Result<usize> {
+        loop {
+            match self.reader.read(self.buf.unfilled()) {
+                Ok(len) => {
+                    self.buf.fill(len);
+                    self.bytes_read += len as u64;
+                    return Ok(len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Consumes `len` available bytes and returns a shared reference to them.
+    fn consume(&mut self, len: usize) -> Bytes {
+        self.buf.consume_bytes(len)
+    }
+
+    /// Moves the available bytes to the front of the current buffer, so the
+    /// consumed bytes are reclaimed for future reads, when nothing references
+    /// the buffer. Otherwise, it is left untouched, since moving would change
+    /// the data which outstanding `Buf` and `Bytes` observe.
+    pub fn compact_buffer(&mut self) {
+        let consumed = self.buf.consumed().len();
+        if self.buf.compact() {
+            self.base_offset += consumed;
+        }
+    }
+
+    /// Replaces the current buffer with a new buffer, which has a capacity of
+    /// at least `min_capacity` and at most `max_capacity` (but always room for
+    /// more than the available bytes), and moves the available bytes to it.
+    /// The old buffer is left untouched for any outstanding references. A
+    /// buffer reused from the pool may be larger than `max_capacity`.
+    fn grow(&mut self, min_capacity: usize, max_capacity: usize) {
+        self.base_offset += self.buf.consumed().len();
+        let partial = self.buf.available();
+        let capacity = self
+            .growth
+            .capacity(partial.len(), self.initial_capacity)
+            .max(min_capacity)
+            .min(max_capacity)
+            .max(partial.len().saturating_add(1));
+        let mut new_buf = match (&mut self.pool, &mut self.make_buf) {
+            (Some(pool), _) => pool.take(capacity),
+            (None, Some(make_buf)) => {
+                let buf = make_buf(capacity);
+                assert_allocated(&buf, capacity);
+                buf
+            }
+            (None, None) => BufMut::new(capacity),
         };
-        let line = self.buf.consume(len).into();
-        Ok(Bytes {
-            buf: self.buf.borrow(),
-            slice: line,
+        self.last_line_copied |= !partial.is_empty();
+        new_buf.append(partial);
+        let old_buf = mem::replace(&mut self.buf, new_buf);
+        if let Some(pool) = &mut self.pool {
+            pool.recycle(old_buf);
+        }
+    }
+
+    /// Reads all of the lines until EOF and pairs them with the buffers they
+    /// were read from, which are each stored once.
+    pub fn read_all_lines(&mut self) -> io::Result<BufPair<Vec<Bytes>>> {
+        BufPair::new(self, |builder| {
+            let mut lines = Vec::new();
+            loop {
+                let line = builder.read_line_bytes()?;
+                if line.is_empty() {
+                    break Ok(lines);
+                }
+                lines.push(line);
+            }
         })
     }
 
+    /// Returns an iterator over the lines of this reader, which ends at EOF.
+    pub fn lines(&mut self) -> Lines<'_, R> {
+        Lines {
+            reader: self,
+            exhausted: false,
+        }
+    }
+
     pub fn buffer(&self) -> Buf {
         self.buf.borrow()
     }
+
+    /// Returns a mark at the current position, which can be returned to with
+    /// [`SharedReader::reset`], while the current buffer is not replaced and
+    /// no bytes are read around it.
+    pub fn mark(&self) -> Mark {
+        Mark {
+            buf: self.buf.borrow(),
+            consumed: self.buf.consumed().len(),
+            base_offset: self.base_offset,
+        }
+    }
+
+    /// Rewinds to a mark, so the bytes consumed since it are read again. This
+    /// fails if the buffer it was made in has been replaced, since the bytes
+    /// would need to be moved from the old buffer. It also fails if bytes were
+    /// taken from the underlying reader without the buffer, as by
+    /// [`SharedReader::skip`] or a bypassing [`Read::read`], since they cannot
+    /// be read again.
+    pub fn reset(&mut self, mark: Mark) -> Result<(), ResetError> {
+        if mark.buf != self.buf.borrow()
+            || mark.base_offset != self.base_offset
+            || mark.consumed > self.buf.consumed().len()
+        {
+            return Err(ResetError);
+        }
+        self.buf.unconsume_to(mark.consumed);
+        Ok(())
+    }
+
+    /// Returns the initial capacity for a new buffer.
+    pub fn initial_capacity(&self) -> usize {
+        self.initial_capacity
+    }
+
+    /// Sets the initial capacity for a new buffer. It only applies the next
+    /// time the current buffer is replaced.
+    pub fn set_initial_capacity(&mut self, capacity: usize) {
+        self.initial_capacity = capacity;
+    }
+
+    /// Sets how to size a new buffer, like [`SharedReader::with_growth`]. It
+    /// only applies the next time the current buffer is replaced.
+    pub fn set_growth(&mut self, growth: GrowthStrategy) {
+        self.growth = growth;
+    }
+
+    /// Sets which sequences terminate a line, like
+    /// [`SharedReader::with_line_ending`].
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+        self.peeked_line = None;
+    }
+
+    /// Sets the maximum length of a delimited read, like
+    /// [`SharedReader::with_max_line`], or removes it with `None`.
+    pub fn set_max_line(&mut self, max: Option<usize>) {
+        self.max_line = max;
+        self.peeked_line = None;
+    }
+
+    /// Sets the maximum number of new buffers for a delimited read, like
+    /// [`SharedReader::with_max_buffers`], or removes it with `None`.
+    pub fn set_max_buffers(&mut self, max: Option<usize>) {
+        self.max_buffers = max;
+    }
+
+    /// Returns a shared reference to the bytes which are buffered, but not yet
+    /// consumed, without consuming them. They remain valid after reading more,
+    /// so a peeked region can be retained while deciding how to parse it.
+    pub fn buffered(&self) -> Bytes {
+        Bytes {
+            buf: self.buf.borrow(),
+            slice: self.buf.available().into(),
+        }
+    }
+
+    /// Returns the number of bytes consumed from the current buffer.
+    pub fn consumed_len(&self) -> usize {
+        self.buf.consumed().len()
+    }
+
+    /// Returns the number of bytes which are buffered, but not yet consumed.
+    pub fn available_len(&self) -> usize {
+        self.buf.available().len()
+    }
+
+    /// Returns the total number of bytes consumed from the stream, across all
+    /// buffers. This is the same as [`SharedReader::position`].
+    pub fn total_consumed(&self) -> usize {
+        self.position()
+    }
+
+    /// Returns the total number of bytes read from the underlying reader,
+    /// including those which are buffered, but not yet consumed.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the absolute position in the stream of the next byte to be
+    /// consumed, which is preserved when buffers are replaced.
+    pub fn position(&self) -> usize {
+        self.base_offset + self.buf.consumed().len()
+    }
+
+    /// Consumes this reader and returns the underlying reader. Any buffered,
+    /// but unconsumed data is lost. Use [`SharedReader::into_parts`] to recover
+    /// it.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Consumes this reader and returns the underlying reader and a shared
+    /// reference to the buffered, but unconsumed data.
+    pub fn into_parts(mut self) -> (R, Bytes) {
+        let unconsumed = self.take_available();
+        (self.reader, unconsumed)
+    }
+}
+
+/// Checks that a buffer from the allocator of a reader is empty and has the
+/// requested capacity. Existing data would be read as part of the stream, and
+/// a smaller buffer could have no room to read into, which would be mistaken
+/// for EOF.
+#[track_caller]
+fn assert_allocated(buf: &BufMut, capacity: usize) {
+    assert!(
+        buf.filled().is_empty(),
+        "allocator returned a buffer which is not empty",
+    );
+    assert!(
+        buf.capacity() >= capacity,
+        "allocator returned a buffer with capacity {}, but {capacity} was requested",
+        buf.capacity(),
+    );
+}
+
+fn line_too_long(limit: usize) -> io::Error {
+    ReaderError::LineTooLong { limit }.into()
+}
+
+/// A position in a [`SharedReader`], which can be returned to. Created by
+/// [`SharedReader::mark`].
+///
+/// It retains the buffer it was made in.
+#[derive(Clone, Debug)]
+pub struct Mark {
+    buf: Buf,
+    consumed: usize,
+    /// The bytes consumed from the stream, other than from the buffer, when
+    /// it was made.
+    base_offset: usize,
+}
+
+/// The error when resetting to a [`Mark`] in a buffer which has been replaced,
+/// or past bytes which were not buffered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResetError;
+
+impl Display for ResetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("mark is in a replaced buffer or before unbuffered bytes")
+    }
+}
+
+impl Error for ResetError {}
+
+/// An error from a [`SharedReader`], which distinguishes its failures.
+///
+/// Most methods return [`io::Error`] for compatibility with `std::io`. For the
+/// failures specific to this reader, it wraps a `ReaderError`, which can be
+/// recovered by converting it back with [`ReaderError::from`].
+#[derive(Debug)]
+pub enum ReaderError {
+    /// An error from the underlying reader.
+    Io(io::Error),
+    /// A delimited read exceeded the maximum line length.
+    LineTooLong { limit: usize },
+    /// A delimited read replaced the buffer more than the maximum number of
+    /// times.
+    TooManyBuffers { limit: usize },
+    /// EOF was reached before the expected number of bytes were available.
+    UnexpectedEof { expected: usize, available: usize },
+    /// A varint overflowed `usize` or was not in its shortest form.
+    InvalidVarint,
+}
+
+impl Display for ReaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReaderError::Io(err) => Display::fmt(err, f),
+            ReaderError::LineTooLong { limit } => {
+                write!(f, "line exceeds maximum length of {limit} bytes")
+            }
+            ReaderError::TooManyBuffers { limit } => {
+                write!(f, "line needs more than the maximum of {limit} new buffers")
+            }
+            ReaderError::UnexpectedEof {
+                expected,
+                available,
+            } => write!(
+                f,
+                "failed to fill whole buffer: expected {expected} bytes, but reached EOF after {available}",
+            ),
+            ReaderError::InvalidVarint => f.write_str("invalid varint"),
+        }
+    }
+}
+
+impl Error for ReaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReaderError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Unwraps a `ReaderError`, if the error wraps one, or otherwise wraps the
+/// error as [`ReaderError::Io`].
+impl From<io::Error> for ReaderError {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<ReaderError>()) {
+            let inner = err.into_inner().unwrap();
+            *inner.downcast::<ReaderError>().unwrap()
+        } else {
+            ReaderError::Io(err)
+        }
+    }
+}
+
+impl From<ReaderError> for io::Error {
+    fn from(err: ReaderError) -> Self {
+        let kind = match err {
+            ReaderError::Io(err) => return err,
+            ReaderError::LineTooLong { .. }
+            | ReaderError::TooManyBuffers { .. }
+            | ReaderError::InvalidVarint => io::ErrorKind::InvalidData,
+            ReaderError::UnexpectedEof { .. } => io::ErrorKind::UnexpectedEof,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// An iterator over the lines of a [`SharedReader`], which yields shared
+/// references to each line. Created by [`SharedReader::lines`].
+///
+/// Once it reaches EOF, it does not read from the reader again. After a line
+/// which exceeds a limit, it yields the error and continues with the next line.
+pub struct Lines<'a, R> {
+    reader: &'a mut SharedReader<R>,
+    exhausted: bool,
+}
+
+impl<R: Read> Iterator for Lines<'_, R> {
+    type Item = io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        match self.reader.read_line() {
+            Ok(line) if line.is_empty() => {
+                self.exhausted = true;
+                None
+            }
+            line => Some(line),
+        }
+    }
+}
+
+impl<R: Read> FusedIterator for Lines<'_, R> {}
+
+impl<R: Read> Read for SharedReader<R> {
+    /// Reads from the available bytes, if there are any. Otherwise, it reads
+    /// directly from the underlying reader, bypassing the buffer.
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.buf.available();
+        if available.is_empty() {
+            let len = self.reader.read(out)?;
+            self.base_offset += len;
+            self.bytes_read += len as u64;
+            return Ok(len);
+        }
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(self.buf.consume(len));
+        Ok(len)
+    }
+}
+
+/// Consuming only advances past the bytes and never overwrites them, so any
+/// retained `Bytes` remain valid.
+///
+/// Methods like `read_line` and `read_until` are shadowed by the inherent
+/// methods of the same names, so they must be called through the trait. On the
+/// other hand, `BufRead::lines` takes `self` by value, so it shadows
+/// [`SharedReader::lines`] when `BufRead` is in scope.
+impl<R: Read> BufRead for SharedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buf.available().is_empty() {
+            if self.buf.unfilled().is_empty() {
+                self.grow(0, usize::MAX);
+            }
+            self.fill_unfilled()?;
+        }
+        Ok(self.buf.available())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.consume(amt);
+    }
 }
@@ -0,0 +1,289 @@
+//! Thread-safe variants of the buffer types, which are backed by [`Arc`]
+//! instead of [`Rc`](alloc::rc::Rc).
+//!
+//! The persistence invariant is what makes sharing across threads sound: once
+//! data is filled, it is never mutated, so any number of threads may read it
+//! concurrently. The only writer is the single [`ArcBufMut`], which writes to
+//! the unfilled portion, and no shared handle can observe that portion. A
+//! shared handle only observes data filled before it was borrowed, and moving
+//! a handle to another thread synchronizes with that thread, so the writes
+//! happen before any reads through the handle. No further synchronization is
+//! needed.
+
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
+    mem::MaybeUninit,
+    ops::{Deref, Range},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use crate::buffer::INITIALIZE_WINDOW;
+
+/// A reference to a thread-safe buffer and a slice within it.
+///
+/// It is `Send` and `Sync` when `T` is, since the slice is immutable.
+pub struct ArcBytes<T = u8> {
+    buf: ArcBuf<T>,
+    slice: NonNull<[T]>,
+}
+
+// SAFETY: The slice is filled, so it is never mutated, and the buffer which
+// it points into is kept alive by the `ArcBuf`.
+unsafe impl<T: Send + Sync> Send for ArcBytes<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcBytes<T> {}
+
+impl<T: Copy> ArcBytes<T> {
+    pub fn slice(&self) -> &[T] {
+        unsafe { &*self.slice.as_ptr() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slice.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slice.is_empty()
+    }
+
+    /// Returns a sub-range of the slice, which retains the same buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the range is out of bounds of the slice.
+    pub fn subslice(&self, range: Range<usize>) -> ArcBytes<T> {
+        ArcBytes {
+            buf: self.buf.clone(),
+            slice: self.slice()[range].into(),
+        }
+    }
+
+    pub fn buf(&self) -> ArcBuf<T> {
+        self.buf.clone()
+    }
+}
+
+impl<T> Clone for ArcBytes<T> {
+    fn clone(&self) -> Self {
+        ArcBytes {
+            buf: self.buf.clone(),
+            slice: self.slice,
+        }
+    }
+}
+
+impl<T: Copy> Deref for ArcBytes<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice()
+    }
+}
+
+/// A persistent, atomically reference counted buffer. Once data is written, it
+/// cannot be overwritten. Only the portion which was filled when the handle was
+/// borrowed can be accessed from it.
+///
+/// It is `Send` and `Sync` when `T` is, since that portion is immutable.
+pub struct ArcBuf<T = u8> {
+    buf: Arc<UnsafeCell<[MaybeUninit<T>]>>,
+    /// The filled length at the time this handle was borrowed.
+    filled: usize,
+}
+
+// SAFETY: Only the filled prefix, which is never mutated, is accessed.
+unsafe impl<T: Send + Sync> Send for ArcBuf<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcBuf<T> {}
+
+impl<T: Copy> ArcBuf<T> {
+    /// Returns the portion of the buffer which had been filled when this
+    /// handle was borrowed.
+    pub fn as_slice(&self) -> &[T] {
+        let buf = self.buf.get();
+        unsafe { slice::from_raw_parts(buf as *mut T, self.filled) }
+    }
+
+    /// Returns the number of handles to the buffer, including this one, the
+    /// `ArcBufMut` if it still exists, and those retained by any `ArcBytes`.
+    pub fn strong_count(&self) -> usize {
+        Arc::strong_count(&self.buf)
+    }
+}
+
+impl<T> Clone for ArcBuf<T> {
+    fn clone(&self) -> Self {
+        ArcBuf {
+            buf: self.buf.clone(),
+            filled: self.filled,
+        }
+    }
+}
+
+impl<T> Debug for ArcBuf<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArcBuf").finish_non_exhaustive()
+    }
+}
+
+impl<T> PartialEq for ArcBuf<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.buf, &other.buf)
+    }
+}
+
+impl<T> Eq for ArcBuf<T> {}
+
+/// A mutable handle into a thread-safe buffer. Only one such handle can be
+/// created, and it has exclusive mutable access for filling the buffer.
+///
+/// It is `Send` and `Sync` when `T` is. Filling requires `&mut self`, so it
+/// can only happen on one thread at a time, and `&self` only exposes the
+/// filled portion.
+///
+/// ```ignore
+/// +----------+----------------+
+/// | consumed |avail|          |
+/// +----------+-----+----------+
+/// |     filled     | unfilled |
+/// +----------------+----------+
+/// ```
+pub struct ArcBufMut<T = u8> {
+    buf: Arc<UnsafeCell<[MaybeUninit<T>]>>,
+    consumed: usize,
+    filled: usize,
+    /// The length of the initialized prefix, which is at least `filled`.
+    initialized: usize,
+}
+
+// SAFETY: The unfilled portion is only accessed through `&mut self` and is
+// never observed by any `ArcBuf` or `ArcBytes`.
+unsafe impl<T: Send + Sync> Send for ArcBufMut<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcBufMut<T> {}
+
+impl<T: Copy> ArcBufMut<T> {
+    pub fn new(capacity: usize) -> Self {
+        let buf = Arc::<[T]>::new_uninit_slice(capacity);
+        // SAFETY: `UnsafeCell<T>` has the same in-memory representation as `T`.
+        let buf =
+            unsafe { Arc::from_raw(Arc::into_raw(buf) as *const UnsafeCell<[MaybeUninit<T>]>) };
+        ArcBufMut {
+            buf,
+            consumed: 0,
+            filled: 0,
+            initialized: 0,
+        }
+    }
+
+    pub fn consumed(&self) -> &[T] {
+        let buf = self.buf.get();
+        unsafe { slice::from_raw_parts(buf as *mut T, self.consumed) }
+    }
+
+    pub fn available(&self) -> &[T] {
+        let buf = self.buf.get();
+        unsafe {
+            let ptr = (buf as *mut T).add(self.consumed);
+            slice::from_raw_parts(ptr, self.filled - self.consumed)
+        }
+    }
+
+    /// Returns the initialized prefix of the unfilled portion, which is
+    /// bounded like [`BufMut::unfilled`](crate::buffer::BufMut::unfilled).
+    pub fn unfilled(&mut self) -> &mut [T]
+    where
+        T: Default,
+    {
+        let buf = self.buf.get();
+        let window = self.filled.saturating_add(INITIALIZE_WINDOW).min(buf.len());
+        self.initialize(window);
+        unsafe {
+            let ptr = (buf as *mut T).add(self.filled);
+            slice::from_raw_parts_mut(ptr, self.initialized.max(window) - self.filled)
+        }
+    }
+
+    pub fn consume(&mut self, n: usize) -> &[T] {
+        // Checked, so a huge `n` cannot wrap around and pass.
+        assert!(
+            self.consumed
+                .checked_add(n)
+                .is_some_and(|end| end <= self.filled)
+        );
+        let buf = self.buf.get();
+        let consumed = unsafe {
+            let ptr = (buf as *mut T).add(self.consumed);
+            slice::from_raw_parts(ptr, n)
+        };
+        self.consumed += n;
+        consumed
+    }
+
+    pub fn fill(&mut self, n: usize) -> &[T]
+    where
+        T: Default,
+    {
+        let buf = self.buf.get();
+        assert!(
+            self.filled
+                .checked_add(n)
+                .is_some_and(|end| end <= buf.len())
+        );
+        self.initialize(self.filled + n);
+        let filled = unsafe {
+            let ptr = (buf as *mut T).add(self.filled);
+            slice::from_raw_parts(ptr, n)
+        };
+        self.filled += n;
+        filled
+    }
+
+    /// Appends the data to the unfilled portion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data does not fit in the unfilled portion.
+    pub fn append(&mut self, data: &[T]) {
+        let buf = self.buf.get();
+        assert!(data.len() <= buf.len() - self.filled);
+        unsafe {
+            let ptr = (buf as *mut T).add(self.filled);
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+        }
+        self.filled += data.len();
+        self.initialized = self.initialized.max(self.filled);
+    }
+
+    /// Fills the buffer with default values from the initialized watermark up
+    /// to `len`. Elements past `filled` are not shared, so they can be written.
+    fn initialize(&mut self, len: usize)
+    where
+        T: Default,
+    {
+        if len > self.initialized {
+            let buf = self.buf.get();
+            for i in self.initialized..len {
+                unsafe { (buf as *mut T).add(i).write(T::default()) };
+            }
+            self.initialized = len;
+        }
+    }
+
+    pub fn borrow(&self) -> ArcBuf<T> {
+        ArcBuf {
+            buf: self.buf.clone(),
+            filled: self.filled,
+        }
+    }
+
+    /// Consumes `n` available elements and returns a shared reference to them,
+    /// which can be sent to other threads.
+    pub fn consume_bytes(&mut self, n: usize) -> ArcBytes<T> {
+        let slice = self.consume(n).into();
+        ArcBytes {
+            buf: self.borrow(),
+            slice,
+        }
+    }
+}
@@ -1,9 +1,20 @@
 use std::{
     borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
     io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    rc::Rc,
+    thread,
 };
 
-use crate::{pair::BufPair, reader::SharedReader};
+use crate::{
+    buffer::{BufMut, BufPool, Bytes},
+    pair::BufPair,
+    reader::{GrowthStrategy, LineEnding, ReaderError, ResetError, SharedReader},
+    sync::ArcBufMut,
+};
 
 struct LimitReader<R> {
     reader: R,
@@ -21,55 +32,15 @@ impl<R: Read> Read for LimitReader<R> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct Ident<'a> {
-    author: &'a [u8],
-    committer: &'a [u8],
-}
-
-#[test]
-fn self_ref() {
-    let s = "author: Author
-committer: Committer";
-    let mut b = LimitReader::new(s.as_bytes(), 8);
-    let mut r = SharedReader::new(&mut b, 100);
-
-    let ident = BufPair::new(&mut r, |builder| -> io::Result<_> {
-        let author = strip_lf(builder.read_line()?);
-        let Some(author) = author.strip_prefix(b"author: ") else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "expected author directive",
-            ));
-        };
-        let committer = strip_lf(builder.read_line()?);
-        let Some(committer) = committer.strip_prefix(b"committer: ") else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "expected committer directive",
-            ));
-        };
-        // Does not compile: lifetime may not live long enough
-        Ok(Ident { author, committer })
-    })
-    .unwrap();
-    assert_eq!(
-        ident.dependent(),
-        &Ident {
-            author: b"Author",
-            committer: b"Committer"
-        }
-    );
-}
-
-#[test]
-fn read_line() {
-    let s = "Lorem ipsum dolor sit amet,
+const LOREM: &str = "Lorem ipsum dolor sit amet,
 consectetur adipiscing elit,
 sed do eiusmod tempor incididunt ut labore et dolore magna aliqua.
 
 Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.";
-    let mut b = LimitReader::new(s.as_bytes(), 8);
+
+#[test]
+fn read_line() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
     let mut r = SharedReader::new(&mut b, 100);
 
     let init_buf = r.buffer();
@@ -97,6 +68,1681 @@ Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliqu
     let line6 = r.read_line().unwrap();
     assert_eq!(utf8(line6.slice()), "");
     assert_eq!(line6.buf(), line5.buf());
+
+    assert_eq!(utf8(init_buf.as_slice()), "");
+    assert_eq!(
+        utf8(line2.buf().as_slice()),
+        "Lorem ipsum dolor sit amet,\nconsectetur adipiscing elit,\nsed do ",
+    );
+}
+
+#[test]
+fn read_until() {
+    let s = b"first\0second record\0\0last";
+    let mut b = LimitReader::new(s.as_slice(), 4);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.read_until(b'\0').unwrap(), b"first\0".as_slice());
+    assert_eq!(r.read_until(b'\0').unwrap(), b"second record\0".as_slice());
+    assert_eq!(r.read_until(b'\0').unwrap(), b"\0".as_slice());
+    assert_eq!(r.read_until(b'\0').unwrap(), "last");
+    assert_eq!(r.read_until(b'\0').unwrap(), "");
+}
+
+#[test]
+fn read_line_crlf() {
+    // The reads split the first CRLF and the buffer growth splits the second.
+    let s = "Host: a\r\nAccept: */*\r\nbare\n\r\nend\r";
+    let mut b = LimitReader::new(s.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 21);
+
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "Host: a");
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "Accept: */*");
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "bare");
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "");
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "end\r");
+    assert_eq!(utf8(r.read_line_crlf().unwrap().slice()), "");
+
+    // The configured line ending does not change where it splits.
+    for line_ending in [LineEnding::Cr, LineEnding::CrLf] {
+        let mut r = SharedReader::with_line_ending("bare\nx\r\ny".as_bytes(), 4, line_ending);
+        assert_eq!(r.read_line_crlf().unwrap(), "bare");
+        assert_eq!(r.read_line_crlf().unwrap(), "x");
+        assert_eq!(r.read_line_crlf().unwrap(), "y");
+    }
+}
+
+#[test]
+fn max_line() {
+    let s = "short\n16 bytes, w/ LF\n17 bytes, w/ LF!\n";
+    let mut b = LimitReader::new(s.as_bytes(), 4);
+    let mut r = SharedReader::with_max_line(&mut b, 4, 16);
+
+    assert_eq!(utf8(r.read_line().unwrap().slice()), "short\n");
+    assert_eq!(utf8(r.read_line().unwrap().slice()), "16 bytes, w/ LF\n");
+    let err = r.read_line().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn read_until_any() {
+    let s = b"let x\t=\n1;";
+    let mut b = LimitReader::new(s.as_slice(), 3);
+    let mut r = SharedReader::new(&mut b, 4);
+
+    let mut tokens = Vec::new();
+    loop {
+        let (token, delim) = r.read_until_any(b" \t\n").unwrap();
+        tokens.push((utf8(token.slice()).to_owned(), delim));
+        if delim.is_none() {
+            break;
+        }
+    }
+    assert_eq!(
+        tokens,
+        [
+            ("let ".to_owned(), Some(b' ')),
+            ("x\t".to_owned(), Some(b'\t')),
+            ("=\n".to_owned(), Some(b'\n')),
+            ("1;".to_owned(), None),
+        ],
+    );
+}
+
+#[test]
+fn read_until_seq() {
+    // The first read splits the first delimiter.
+    let s = b"one--two---three-four--";
+    let mut b = LimitReader::new(s.as_slice(), 4);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.read_until_seq(b"--").unwrap().slice(), b"one--");
+    assert_eq!(r.read_until_seq(b"--").unwrap().slice(), b"two--");
+    assert_eq!(r.read_until_seq(b"").unwrap().slice(), b"");
+    assert_eq!(r.read_until_seq(b"--").unwrap().slice(), b"-three-four--");
+    assert_eq!(r.read_until_seq(b"--").unwrap().slice(), b"");
+}
+
+#[test]
+fn read_exact() {
+    let s = b"\x00\x00\x00\x05hello\x00\x00\x00\x0cfirst\nsecondtruncated";
+    let mut b = LimitReader::new(s.as_slice(), 3);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    let len1 = r.read_exact(4).unwrap();
+    let frame1 = r.read_exact(5).unwrap();
+    assert_eq!(r.peek(4).unwrap(), b"\x00\x00\x00\x0c");
+    let len2 = r.read_exact(4).unwrap();
+    let frame2 = r.read_exact(12).unwrap();
+    assert_eq!(len1.slice(), b"\x00\x00\x00\x05");
+    assert_eq!(frame1.slice(), b"hello");
+    assert_eq!(len2.slice(), b"\x00\x00\x00\x0c");
+    assert_eq!(frame2.slice(), b"first\nsecond");
+    assert_ne!(frame2.buf(), len2.buf());
+    let err = r.read_exact(10).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(r.peek(10).unwrap(), b"truncated");
+    assert_eq!(r.read_line().unwrap().slice(), b"truncated");
+}
+
+#[test]
+fn read_mixed() {
+    let s = "header\nrest of the stream";
+    let mut b = LimitReader::new(s.as_bytes(), 10);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    assert_eq!(utf8(r.read_line().unwrap().slice()), "header\n");
+    let mut rest = String::new();
+    r.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "rest of the stream");
+}
+
+#[test]
+fn into_parts() {
+    let s = "header\nbody";
+    let mut b = LimitReader::new(s.as_bytes(), 9);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    assert_eq!(utf8(r.read_line().unwrap().slice()), "header\n");
+    let (b, unconsumed) = r.into_parts();
+    assert_eq!(utf8(unconsumed.slice()), "bo");
+    let mut rest = String::new();
+    b.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "dy");
+}
+
+#[test]
+fn lines() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let lines = r.lines().collect::<io::Result<Vec<_>>>().unwrap();
+    let lines = lines
+        .iter()
+        .map(|line| utf8(line.slice()))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, LOREM.split_inclusive('\n').collect::<Vec<_>>());
+}
+
+#[test]
+fn growth_strategy() {
+    let strategies = [
+        GrowthStrategy::Double,
+        GrowthStrategy::Fixed(4),
+        GrowthStrategy::Additive(1),
+    ];
+    for growth in strategies {
+        let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+        let mut r = SharedReader::with_growth(&mut b, 4, growth);
+        let lines = r.lines().collect::<io::Result<Vec<_>>>().unwrap();
+        let lines = lines
+            .iter()
+            .map(|line| utf8(line.slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, LOREM.split_inclusive('\n').collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn strong_count() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let buf = r.buffer();
+    assert_eq!(buf.strong_count(), 2);
+    assert_eq!(buf.capacity(), 100);
+    assert_eq!(buf.filled_len(), 0);
+    let line1 = r.read_line().unwrap();
+    let line2 = r.read_line().unwrap();
+    assert_eq!(buf.strong_count(), 4);
+    drop(line1);
+    drop(line2);
+    assert_eq!(buf.strong_count(), 2);
+    let line3 = r.read_line().unwrap();
+    assert_ne!(line3.buf(), buf);
+    assert_eq!(buf.strong_count(), 1);
+}
+
+#[test]
+fn bytes_clone() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let line = r.read_line().unwrap();
+    let clone = line.clone();
+    drop(r);
+    drop(line);
+    assert_eq!(utf8(clone.slice()), "Lorem ipsum dolor sit amet,\n");
+}
+
+#[test]
+fn bytes_to_vec() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let line1 = r.read_line().unwrap();
+    let line2 = r.read_line().unwrap();
+    let buf = line1.buf();
+    let owned1 = line1.to_vec();
+    let owned2 = line2.into_owned();
+    drop(r);
+    drop(line1);
+    assert_eq!(buf.strong_count(), 1);
+    drop(buf);
+    assert_eq!(owned1, b"Lorem ipsum dolor sit amet,\n");
+    assert_eq!(owned2, b"consectetur adipiscing elit,\n");
+}
+
+#[test]
+// The hashed slice is persistent, despite the `UnsafeCell` in the buffer.
+#[allow(clippy::mutable_key_type)]
+fn bytes_hash_set() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let line = r.read_line().unwrap();
+    let mut words = HashSet::new();
+    words.insert(line.subslice(0..5));
+    words.insert(line.subslice(6..11));
+    words.insert(line.subslice(0..11));
+    assert!(!words.insert(line.subslice(0..5)));
+    assert!(words.contains(b"Lorem".as_slice()));
+    assert!(words.contains(b"ipsum".as_slice()));
+    assert!(words.contains(b"Lorem ipsum".as_slice()));
+    assert!(!words.contains(b"dolor".as_slice()));
+}
+
+#[test]
+fn append_partial() {
+    let mut buf = BufMut::new(10);
+    assert_eq!(buf.append_partial(b"hello, "), 7);
+    assert_eq!(buf.append_partial(b"world"), 3);
+    assert_eq!(buf.append_partial(b"!"), 0);
+    assert_eq!(buf.available(), b"hello, wor");
+    assert!(buf.unfilled().is_empty());
+}
+
+#[test]
+fn generic_elements() {
+    let mut buf = BufMut::<u16>::new(4);
+    buf.append(&[1, 2]);
+    buf.unfilled()[0] = 3;
+    assert_eq!(buf.fill(1), &[3]);
+    assert_eq!(buf.available(), &[1, 2, 3]);
+    assert_eq!(buf.consume(2), &[1, 2]);
+    assert_eq!(buf.available(), &[3]);
+    assert_eq!(buf.borrow().as_slice(), &[1, 2, 3]);
+    assert_eq!(buf.unfilled(), &[0]);
+}
+
+#[test]
+fn reserve() {
+    let mut buf = BufMut::new(8);
+    buf.append(b"abcdef");
+    assert!(buf.reserve(2));
+    buf.consume(4);
+    assert!(buf.reserve(6));
+    assert_eq!(buf.available(), b"ef");
+    assert_eq!(buf.unfilled().len(), 6);
+
+    let shared = buf.borrow();
+    buf.append(b"gh");
+    assert!(!buf.reserve(6));
+    assert_eq!(buf.available(), b"efgh");
+    assert!(buf.unfilled().len() >= 6);
+    assert_eq!(shared.as_slice(), b"ef");
+}
+
+#[test]
+fn clear() {
+    let mut buf = BufMut::new(8);
+    buf.append(b"message");
+    buf.consume(3);
+    buf.clear();
+    assert_eq!(buf.available(), b"");
+    assert_eq!(buf.unfilled().len(), 8);
+
+    buf.append(b"shared");
+    let shared = buf.borrow();
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.clear()));
+    assert!(res.is_err());
+    assert_eq!(shared.as_slice(), b"shared");
+}
+
+#[test]
+fn read_to_end() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 16);
+
+    let line = r.read_line().unwrap();
+    let rest = r.read_to_end().unwrap();
+    assert_eq!(utf8(line.slice()), "Lorem ipsum dolor sit amet,\n");
+    assert_eq!(utf8(rest.slice()), &LOREM[line.len()..]);
+    assert_eq!(rest.buf(), r.buffer());
+    assert_eq!(r.read_to_end().unwrap().slice(), b"");
+}
+
+#[test]
+fn pair_read_exact() {
+    let s = "\x7fSRBheader line\nnext";
+    let mut b = LimitReader::new(s.as_bytes(), 3);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        let magic = builder.read_exact(4)?;
+        let line = builder.read_line()?;
+        assert_eq!(magic, b"\x7fSRB");
+        assert_eq!(line, b"header line\n");
+        Ok((magic.len(), line.len()))
+    })
+    .unwrap();
+    assert_eq!(pair.dependent(), &(4, 12));
+    let owner = pair.owner();
+    assert_eq!(owner.len(), 2);
+    assert_eq!(&owner[0].as_slice()[..4], b"\x7fSRB");
+    assert_eq!(&owner[1].as_slice()[..12], b"header line\n");
+}
+
+#[test]
+fn sync_across_threads() {
+    let mut buf = ArcBufMut::new(16);
+    buf.append(b"first,second");
+    let first = buf.consume_bytes(6);
+    let second = buf.consume_bytes(6);
+    let worker = thread::spawn(move || {
+        assert_eq!(first.slice(), b"first,");
+        first
+    });
+    buf.append(b"!");
+    assert_eq!(second.slice(), b"second");
+    let first = worker.join().unwrap();
+    assert_eq!(first.buf(), second.buf());
+    assert_eq!(first.buf().as_slice(), b"first,second");
+}
+
+#[test]
+fn position() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 3);
+    let mut r = SharedReader::new(&mut b, 10);
+
+    let mut offset = 0;
+    let mut bufs = vec![r.buffer()];
+    assert_eq!(r.position(), 0);
+    for line in LOREM.split_inclusive('\n') {
+        let read = r.read_line().unwrap();
+        assert_eq!(utf8(read.slice()), line);
+        offset += line.len();
+        assert_eq!(r.position(), offset);
+        if bufs.last() != Some(&read.buf()) {
+            bufs.push(read.buf());
+        }
+    }
+    assert!(bufs.len() > 3);
+    assert_eq!(r.total_consumed(), LOREM.len());
+}
+
+#[test]
+fn pair_and_then() {
+    let s = "2\nfirst\nsecond\n";
+    let mut b = LimitReader::new(s.as_bytes(), 4);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    let header = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        let count = utf8(builder.read_line_trimmed()?).parse::<usize>().unwrap();
+        Ok(count)
+    })
+    .unwrap();
+    assert_eq!(header.owner().len(), 1);
+    let body = header
+        .and_then(&mut r, |count, builder| -> io::Result<_> {
+            let mut lens = Vec::new();
+            for _ in 0..count {
+                lens.push(builder.read_line_trimmed()?.len());
+            }
+            Ok(lens)
+        })
+        .unwrap();
+    assert_eq!(body.dependent(), &[5, 6]);
+    // The header and the first line share a buffer.
+    assert_eq!(body.owner().len(), 2);
+    assert_eq!(&body.owner()[0].as_slice()[..2], b"2\n");
+}
+
+#[test]
+fn buf_read() {
+    let mut b1 = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r1 = SharedReader::new(&mut b1, 16);
+    let mut b2 = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r2 = SharedReader::new(&mut b2, 16);
+    let mut b3 = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r3 = SharedReader::new(&mut b3, 16);
+
+    loop {
+        let mut line = Vec::new();
+        io::BufRead::read_until(&mut r1, b'\n', &mut line).unwrap();
+        let mut into = Vec::new();
+        let len = r3.read_line_into(&mut into).unwrap();
+        assert_eq!(into, line);
+        assert_eq!(len, line.len());
+        let native = r2.read_line().unwrap();
+        assert_eq!(line, native.slice());
+        if line.is_empty() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn pair_compact() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 32);
+    let words = r
+        .lines()
+        .map(|line| line.map(|line| line.subslice(0..line.len().min(2))))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    let old_buf = words[0].buf();
+    assert!(old_buf.strong_count() > 1);
+
+    let mut pair = BufPair::new(&mut r, |_| io::Result::Ok(words)).unwrap();
+    pair.compact();
+    assert_eq!(old_buf.strong_count(), 1);
+    assert_eq!(pair.owner().len(), 1);
+    assert_eq!(pair.owner()[0].capacity(), 9);
+    assert_eq!(pair.owner()[0].as_slice(), b"Locose\nUt");
+    let words = pair
+        .dependent()
+        .iter()
+        .map(|word| utf8(word.slice()))
+        .collect::<Vec<_>>();
+    assert_eq!(words, ["Lo", "co", "se", "\n", "Ut"]);
+}
+
+#[test]
+fn pair_read_line_bytes() {
+    let mut r = SharedReader::new(LOREM.as_bytes(), 16);
+
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        let first = builder.read_line_bytes()?;
+        let second = builder.read_line_bytes()?;
+        Ok(vec![first, second])
+    })
+    .unwrap();
+    let lines = pair.dependent();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].slice().ends_with(b"\n"));
+    assert!(lines[1].slice().ends_with(b"\n"));
+    for line in lines {
+        assert!(pair.owner().contains(&line.buf()));
+    }
+}
+
+#[test]
+fn skip() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.read_exact(2).unwrap().slice(), b"Lo");
+    let buf = r.buffer();
+    // Skip the 3 buffered bytes and 100 more from the reader.
+    assert_eq!(r.skip(103).unwrap(), 103);
+    assert_eq!(r.position(), 105);
+    assert_eq!(r.buffer(), buf);
+    assert_eq!(
+        r.read_exact(4).unwrap().slice(),
+        &LOREM.as_bytes()[105..109]
+    );
+
+    let rest = LOREM.len() - 109;
+    assert_eq!(r.skip(usize::MAX).unwrap(), rest);
+    assert_eq!(r.position(), LOREM.len());
+    assert_eq!(r.skip(1).unwrap(), 0);
+}
+
+#[test]
+fn trim_ascii() {
+    let mut buf = BufMut::new(32);
+    buf.append(b" \t key: value \t\n\t \t");
+    let field = buf.consume_bytes(16);
+    let blank = buf.consume_bytes(3);
+
+    assert_eq!(field.trim_ascii().slice(), b"key: value");
+    assert_eq!(field.trim_ascii_start().slice(), b"key: value \t\n");
+    assert_eq!(field.trim_ascii_end().slice(), b" \t key: value");
+    assert_eq!(field.trim_ascii().buf(), field.buf());
+    assert!(blank.trim_ascii().is_empty());
+    assert!(blank.trim_ascii_start().is_empty());
+    assert!(blank.trim_ascii_end().is_empty());
+}
+
+#[test]
+fn bytes_split() {
+    let mut r = SharedReader::new("id,name,,email\n".as_bytes(), 32);
+    let line = r.read_line().unwrap().trim_ascii_end();
+
+    let fields = line.split(b',').collect::<Vec<_>>();
+    assert_eq!(fields.len(), 4);
+    let fields_str = fields.iter().map(|f| utf8(f)).collect::<Vec<_>>();
+    assert_eq!(fields_str, ["id", "name", "", "email"]);
+    for field in &fields {
+        assert_eq!(field.buf(), line.buf());
+    }
+    assert_eq!(line.subslice(0..0).split(b',').count(), 1);
+    assert_eq!(line.subslice(0..3).split(b',').count(), 2);
+}
+
+#[test]
+fn buf_content_eq() {
+    let mut a = BufMut::new(8);
+    let mut b = BufMut::new(16);
+    a.append(b"abc");
+    b.append(b"abc");
+    assert_ne!(a.borrow(), b.borrow());
+    assert!(a.borrow().content_eq(&b.borrow()));
+    let before = a.borrow();
+    a.append(b"d");
+    assert!(!a.borrow().content_eq(&b.borrow()));
+    assert!(before.content_eq(&b.borrow()));
+}
+
+#[test]
+fn with_buffer() {
+    let mut buf = BufMut::new(16);
+    buf.append(b"skip,first li");
+    buf.consume(5);
+    let mut r = SharedReader::with_buffer("ne\nsecond\n".as_bytes(), buf);
+
+    assert_eq!(r.available_len(), 8);
+    assert_eq!(r.read_line().unwrap().slice(), b"first line\n");
+    assert_eq!(r.read_line().unwrap().slice(), b"second\n");
+    assert_eq!(r.position(), 23);
+}
+
+#[test]
+fn read_chunk() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.read_exact(2).unwrap().slice(), b"Lo");
+    assert_eq!(r.read_chunk().unwrap().slice(), b"rem");
+    let buf = r.buffer();
+    assert_eq!(r.read_chunk().unwrap().slice(), b" ip");
+    // The buffer is full, so a new one is started.
+    let chunk = r.read_chunk().unwrap();
+    assert_eq!(chunk.slice(), b"sum d");
+    assert_ne!(chunk.buf(), buf);
+    assert_eq!(chunk.buf().capacity(), 8);
+
+    let mut chunks = Vec::new();
+    loop {
+        let chunk = r.read_chunk().unwrap();
+        if chunk.is_empty() {
+            break;
+        }
+        assert!(chunk.len() <= 5);
+        chunks.extend_from_slice(&chunk);
+    }
+    assert_eq!(chunks, &LOREM.as_bytes()[13..]);
+}
+
+#[test]
+fn bytes_debug() {
+    let mut buf = BufMut::new(32);
+    buf.append(b"author: \"A\xffuthor\"\n");
+    let bytes = buf.consume_bytes(18);
+    let debug = format!("{bytes:?}");
+    assert!(debug.starts_with(r#"Bytes { slice: "author: \"A\xffuthor\"\n", buf: 0x"#));
+    assert!(debug.ends_with(", len: 18 }"));
+    let other = buf.borrow();
+    assert!(debug.contains(&format!("{:p}", other.as_slice().as_ptr())));
+}
+
+#[test]
+fn buffered() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.peek(7).unwrap(), b"Lorem i");
+    let lookahead = r.buffered();
+    assert_eq!(lookahead, "Lorem ip");
+    assert_eq!(r.read_line().unwrap().len(), LOREM.find('\n').unwrap() + 1);
+    assert_eq!(lookahead, "Lorem ip");
+}
+
+#[test]
+fn append_growing() {
+    let mut buf = BufMut::new(4);
+    let first = buf.append_growing(b"abc");
+    let bytes = buf.consume_bytes(2);
+    assert_eq!(buf.append_growing(b"d"), first);
+
+    // Shared by `first` and `bytes`, so it is copied to a new buffer.
+    let second = buf.append_growing(b"efg");
+    assert_ne!(second, first);
+    assert_eq!(second.as_slice(), b"cdefg");
+    assert_eq!(first.as_slice(), b"abc");
+    assert_eq!(bytes, "ab");
+
+    drop(second);
+    let third = buf.append_growing(b"hijklmnop");
+    assert_eq!(buf.available(), b"cdefghijklmnop");
+    assert_eq!(third.filled_len(), 14);
+}
+
+#[test]
+fn read_line_str() {
+    let s = b"caf\xc3\xa9\nbad \xff byte\n";
+    let mut b = LimitReader::new(s.as_slice(), 3);
+    let mut r = SharedReader::new(&mut b, 4);
+
+    let (line, s) = r.read_line_str().unwrap();
+    assert_eq!(line, "café\n");
+    assert!(matches!(s, Cow::Borrowed("café\n")));
+    let (line, s) = r.read_line_str().unwrap();
+    assert_eq!(line.slice(), b"bad \xff byte\n");
+    assert!(matches!(s, Cow::Owned(ref s) if s == "bad \u{fffd} byte\n"));
+    let (line, s) = r.read_line_str().unwrap();
+    assert!(line.is_empty() && s.is_empty());
+}
+
+#[test]
+fn lines_fused() {
+    /// A reader which yields more data after reporting EOF.
+    struct Resume(Vec<&'static [u8]>);
+
+    impl Read for Resume {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    let mut b = Resume(vec![b"one\n", b"", b"spurious\n"]);
+    let mut r = SharedReader::new(&mut b, 16);
+    let mut lines = r.lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "one\n");
+    assert!(lines.next().is_none());
+    assert!(lines.next().is_none());
+    assert!(lines.next().is_none());
+    assert_eq!(b.0, [b"spurious\n"]);
+}
+
+#[test]
+fn pair_as_pair() {
+    fn count_lines<T>(pair: BufPair<&Vec<T>>) -> usize {
+        pair.dependent().len()
+    }
+
+    let mut r = SharedReader::new(LOREM.as_bytes(), 16);
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        Ok(vec![builder.read_line_bytes()?, builder.read_line_bytes()?])
+    })
+    .unwrap();
+    let strong_count = pair.owner()[0].strong_count();
+    let borrowed = pair.as_pair();
+    assert_eq!(borrowed.owner(), pair.owner());
+    assert_eq!(pair.owner()[0].strong_count(), strong_count + 1);
+    assert_eq!(count_lines(borrowed), 2);
+    assert_eq!(pair.owner()[0].strong_count(), strong_count);
+}
+
+#[test]
+fn overflowing_offsets() {
+    let mut buf = BufMut::new(8);
+    buf.append(b"abc");
+    buf.consume(1);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.consume(usize::MAX).len()));
+    assert!(res.is_err());
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.fill(usize::MAX).len()));
+    assert!(res.is_err());
+    assert_eq!(buf.available(), b"bc");
+
+    let mut buf = ArcBufMut::new(8);
+    buf.append(b"abc");
+    buf.consume(1);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.consume(usize::MAX).len()));
+    assert!(res.is_err());
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.fill(usize::MAX).len()));
+    assert!(res.is_err());
+    assert_eq!(buf.available(), b"bc");
+}
+
+#[test]
+fn read_while() {
+    // Length-prefixed records, where the length is a decimal number ending in
+    // a colon.
+    fn record(buf: &[u8]) -> Option<usize> {
+        let colon = buf.iter().position(|&b| b == b':')?;
+        let len = utf8(&buf[..colon]).parse::<usize>().unwrap();
+        let end = colon + 1 + len;
+        (end <= buf.len()).then_some(end)
+    }
+
+    let mut b = LimitReader::new(b"5:hello11:hello world3:end".as_slice(), 3);
+    let mut r = SharedReader::new(&mut b, 4);
+
+    assert_eq!(r.read_while(record).unwrap(), "5:hello");
+    assert_eq!(r.read_while(record).unwrap(), "11:hello world");
+    let mut calls = 0;
+    let rest = r.read_while(|buf| {
+        calls += 1;
+        record(buf)
+    });
+    assert_eq!(rest.unwrap(), "3:end");
+    assert!(calls > 1);
+    assert_eq!(r.read_while(|_| None).unwrap(), "");
+}
+
+#[test]
+fn bytes_index() {
+    let mut r = SharedReader::new("author: Author\n".as_bytes(), 32);
+    let line = r.read_line().unwrap();
+    assert_eq!(line[0], b'a');
+    assert_eq!(&line[8..14], b"Author");
+    assert_eq!(&line[8..], b"Author\n");
+    assert_eq!(&line[..6], b"author");
+    assert_eq!(&line[..], line.slice());
+}
+
+#[test]
+fn buf_pool() {
+    let mut pool = BufPool::new(1);
+    let mut buf = pool.take(8);
+    buf.append(b"shared");
+    let bytes = buf.consume_bytes(6);
+    pool.recycle(buf);
+    assert_eq!(pool.len(), 1);
+    // Still referenced, so a new buffer is allocated.
+    let other = pool.take(8);
+    assert_ne!(other.borrow(), bytes.buf());
+    pool.recycle(other);
+    assert_eq!(pool.len(), 1);
+
+    let old = bytes.buf().as_slice().as_ptr();
+    drop(bytes);
+    let buf = pool.take(4);
+    assert!(pool.is_empty());
+    assert_eq!(buf.borrow().as_slice().as_ptr(), old);
+    assert!(buf.available().is_empty());
+
+    // Lines which are dropped as they are read let the reader alternate
+    // between two buffers.
+    let s = "line\n".repeat(20);
+    let mut b = LimitReader::new(s.as_bytes(), 7);
+    let mut r = SharedReader::with_pool(&mut b, 16, BufPool::new(2));
+    // Compare by address, since retaining the buffers would prevent reuse.
+    let mut bufs = HashSet::new();
+    for line in r.lines() {
+        bufs.insert(line.unwrap().buf().as_slice().as_ptr());
+    }
+    assert_eq!(bufs.len(), 2);
+}
+
+#[test]
+fn bytes_try_into_string() {
+    let mut r = SharedReader::new(b"valid\nin\xffvalid\n".as_slice(), 32);
+    let line = r.read_line().unwrap();
+    let buf = line.buf();
+    assert_eq!(String::try_from(line).unwrap(), "valid\n");
+    assert_eq!(buf.strong_count(), 2);
+    let err = String::try_from(r.read_line().unwrap()).unwrap_err();
+    assert_eq!(err.valid_up_to(), 2);
+}
+
+#[test]
+fn peek_line() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    let first = LOREM.split_inclusive('\n').next().unwrap();
+    assert_eq!(r.peek_line().unwrap(), first.as_bytes());
+    assert_eq!(r.peek_line().unwrap(), first.as_bytes());
+    assert_eq!(r.position(), 0);
+    assert_eq!(r.read_line().unwrap(), first);
+    assert_eq!(r.position(), first.len());
+
+    let mut r = SharedReader::new("last".as_bytes(), 8);
+    assert_eq!(r.peek_line().unwrap(), b"last");
+    assert_eq!(r.read_line().unwrap(), "last");
+    assert_eq!(r.peek_line().unwrap(), b"");
+}
+
+#[test]
+fn available_mut() {
+    let mut buf = BufMut::new(16);
+    buf.append(b"rot13");
+    buf.available_mut().make_ascii_uppercase();
+    assert_eq!(buf.available(), b"ROT13");
+
+    let consumed = buf.consume_bytes(5);
+    buf.append(b"abc");
+    let res = panic::catch_unwind(AssertUnwindSafe(|| buf.available_mut().len()));
+    assert!(res.is_err());
+    // `consumed` was borrowed before `abc` was filled, so it cannot observe it.
+    unsafe { buf.available_mut_unchecked() }.make_ascii_uppercase();
+    assert_eq!(buf.available(), b"ABC");
+    assert_eq!(consumed, "ROT13");
+}
+
+#[test]
+fn reader_error() {
+    let mut r = SharedReader::with_max_line("short\nlong line\ntail".as_bytes(), 4, 8);
+    assert_eq!(r.try_read_line().unwrap(), "short\n");
+    let err = r.try_read_line().unwrap_err();
+    assert!(matches!(err, ReaderError::LineTooLong { limit: 8 }));
+    assert_eq!(err.to_string(), "line exceeds maximum length of 8 bytes");
+
+    let err = io::Error::from(err);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        ReaderError::from(err),
+        ReaderError::LineTooLong { limit: 8 }
+    ));
+
+    let mut r = SharedReader::new("tail".as_bytes(), 4);
+    let err = r.try_read_exact(6).unwrap_err();
+    assert!(matches!(
+        err,
+        ReaderError::UnexpectedEof {
+            expected: 6,
+            available: 4
+        }
+    ));
+    let err = ReaderError::from(io::Error::other("other"));
+    assert!(matches!(err, ReaderError::Io(ref err) if err.kind() == io::ErrorKind::Other));
+}
+
+#[test]
+fn set_initial_capacity() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+    assert_eq!(r.initial_capacity(), 8);
+
+    r.read_exact(6).unwrap();
+    let buf = r.buffer();
+    r.set_initial_capacity(64);
+    assert_eq!(r.initial_capacity(), 64);
+    assert_eq!(r.buffer().capacity(), 8);
+    r.read_exact(4).unwrap();
+    assert_ne!(r.buffer(), buf);
+    assert_eq!(r.buffer().capacity(), 64);
+}
+
+#[test]
+fn bytes_buf_range() {
+    let mut r = SharedReader::new("key=value\n".as_bytes(), 16);
+    let line = r.read_line().unwrap();
+    let (key, value) = line.split_at(4);
+    let value = value.trim_ascii_end();
+    assert_eq!(line.buf_offset(), 0);
+    assert_eq!(key.buf_range(), 0..4);
+    assert_eq!(value.buf_range(), 4..9);
+    assert_eq!(&value.buf().as_slice()[value.buf_range()], value.slice());
+
+    let mut buf = BufMut::<u32>::new(4);
+    buf.append(&[1, 2, 3]);
+    buf.consume(1);
+    assert_eq!(buf.consume_bytes(2).buf_range(), 1..3);
+}
+
+#[test]
+fn bytes_ord() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 7);
+    let mut r = SharedReader::new(&mut b, 8);
+    let mut words = Vec::new();
+    for _ in 0..12 {
+        let word = r.read_until(b' ').unwrap();
+        // Overlapping sub-slices of the same buffer.
+        words.push(word.subslice(0..word.len() / 2));
+        words.push(word);
+    }
+    let mut expected = words.iter().map(|w| w.to_vec()).collect::<Vec<_>>();
+    words.sort();
+    expected.sort();
+    let words = words.iter().map(|w| w.to_vec()).collect::<Vec<_>>();
+    assert_eq!(words, expected);
+}
+
+#[test]
+fn line_ending() {
+    let s = b"lf\ncrlf\r\ncr\rcr\r\rend";
+    let read_lines = |line_ending, limit| {
+        let mut b = LimitReader::new(s.as_slice(), limit);
+        let mut r = SharedReader::with_line_ending(&mut b, 4, line_ending);
+        let mut lines = Vec::new();
+        while let Some(line) = r.lines().next() {
+            let line = line.unwrap();
+            assert_eq!(line_ending.trim(&line), line.trim_ascii_end().slice());
+            lines.push(line.to_vec());
+        }
+        lines
+    };
+
+    for limit in 1..4 {
+        let lines = read_lines(LineEnding::Lf, limit);
+        assert_eq!(lines, [&b"lf\n"[..], b"crlf\r\n", b"cr\rcr\r\rend"]);
+        let lines = read_lines(LineEnding::CrLf, limit);
+        assert_eq!(lines, [&b"lf\ncrlf\r\n"[..], b"cr\rcr\r\rend"]);
+        let lines = read_lines(LineEnding::Cr, limit);
+        assert_eq!(
+            lines,
+            [&b"lf\ncrlf\r"[..], b"\ncr\r", b"cr\r", b"\r", b"end"]
+        );
+        let lines = read_lines(LineEnding::Any, limit);
+        assert_eq!(
+            lines,
+            [&b"lf\n"[..], b"crlf\r\n", b"cr\r", b"cr\r", b"\r", b"end"]
+        );
+    }
+
+    // A CR at EOF terminates the line.
+    let mut r = SharedReader::with_line_ending("a\r".as_bytes(), 4, LineEnding::Any);
+    assert_eq!(r.read_line().unwrap(), "a\r");
+    assert_eq!(r.read_line().unwrap(), "");
+}
+
+#[test]
+fn pair_into_dependent() {
+    let mut r = SharedReader::new(LOREM.as_bytes(), 16);
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        Ok(builder.read_line()?.to_vec())
+    })
+    .unwrap();
+    let buf = pair.owner()[0].clone();
+    let strong_count = buf.strong_count();
+    let line = pair.into_dependent();
+    assert_eq!(buf.strong_count(), strong_count - 1);
+    assert!(LOREM.as_bytes().starts_with(&line));
+}
+
+#[test]
+fn buf_mut_from() {
+    let buf = BufMut::from(b"prefilled".as_slice());
+    assert_eq!(buf.available(), b"prefilled");
+    assert!(buf.consumed().is_empty());
+    assert_eq!(buf.borrow().capacity(), 9);
+
+    let buf = BufMut::from(vec![1u16, 2, 3]);
+    assert_eq!(buf.available(), [1, 2, 3]);
+    assert_eq!(buf.borrow().capacity(), 3);
+}
+
+#[test]
+fn take_available() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+    assert_eq!(r.take_available(), "");
+    assert_eq!(r.read_exact(2).unwrap(), "Lo");
+    assert_eq!(r.take_available(), "rem");
+    let empty = r.take_available();
+    assert!(empty.is_empty());
+    assert_eq!(empty.buf(), r.buffer());
+    assert_eq!(r.position(), 5);
+}
+
+#[test]
+fn read_varint_frame() {
+    let mut s = b"\x05hello\x00\x80\x01".to_vec();
+    s.extend_from_slice(&[b'x'; 128]);
+    s.extend_from_slice(b"\x03ab");
+    let mut b = LimitReader::new(s.as_slice(), 3);
+    let mut r = SharedReader::new(&mut b, 4);
+
+    assert_eq!(r.read_varint_frame().unwrap(), "hello");
+    assert_eq!(r.read_varint_frame().unwrap(), "");
+    let frame = r.read_varint_frame().unwrap();
+    assert_eq!(frame.len(), 128);
+    assert!(frame.iter().all(|&b| b == b'x'));
+    let err = r.read_varint_frame().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(r.take_available(), "ab");
+
+    let invalid: [&[u8]; 2] = [b"\x85\x00", &[0xff; 11]];
+    for s in invalid {
+        let mut r = SharedReader::new(s, 4);
+        let err = ReaderError::from(r.read_varint_frame().unwrap_err());
+        assert!(matches!(err, ReaderError::InvalidVarint));
+    }
+    let mut r = SharedReader::new(b"\x85".as_slice(), 4);
+    let err = r.read_varint_frame().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(r.available_len(), 1);
+
+    // A huge length on a short stream is not allocated up front.
+    let mut r = SharedReader::new(b"\xff\xff\xff\xff\xff\xff\xff\x7fabc".as_slice(), 4);
+    let err = ReaderError::from(r.read_varint_frame().unwrap_err());
+    assert!(matches!(
+        err,
+        ReaderError::UnexpectedEof {
+            expected: 0xff_ffff_ffff_ffff,
+            available: 3,
+        }
+    ));
+    assert_eq!(r.take_available(), "abc");
+}
+
+#[test]
+fn bytes_concat() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+    let first = r.read_exact(6).unwrap();
+    let second = r.read_exact(6).unwrap();
+    assert_ne!(first.buf(), second.buf());
+
+    let joined = Bytes::concat(&[first.subslice(0..5), second.clone()]);
+    assert_eq!(joined, "Loremipsum ");
+    assert_ne!(joined.buf(), first.buf());
+    assert_ne!(joined.buf(), second.buf());
+    assert_eq!(joined.buf().capacity(), 11);
+    assert!(Bytes::<u8>::concat(&[]).is_empty());
+}
+
+#[test]
+fn read_line_errors() {
+    /// A reader which yields each chunk or error in turn.
+    struct Chunks(Vec<io::Result<&'static [u8]>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0)?;
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    let mut b = Chunks(vec![
+        Ok(b"par"),
+        Err(io::ErrorKind::Interrupted.into()),
+        Ok(b"tial\nsec"),
+        Err(io::Error::other("failed")),
+        Ok(b"ond\n"),
+    ]);
+    let mut r = SharedReader::new(&mut b, 16);
+    assert_eq!(r.read_line().unwrap(), "partial\n");
+    let err = r.read_line().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(r.available_len(), 3);
+    assert_eq!(r.read_line().unwrap(), "second\n");
+    assert_eq!(r.read_line().unwrap(), "");
+
+    // Chunks are retried when interrupted, like lines.
+    let mut b = Chunks(vec![Err(io::ErrorKind::Interrupted.into()), Ok(b"chunk")]);
+    let mut r = SharedReader::new(&mut b, 16);
+    assert_eq!(r.read_chunk().unwrap(), "chunk");
+    assert_eq!(r.bytes_read(), 5);
+}
+
+#[test]
+fn mark_reset() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 16);
+
+    assert_eq!(r.read_exact(2).unwrap(), "Lo");
+    let mark = r.mark();
+    let rem = r.read_exact(3).unwrap();
+    assert_eq!(rem, "rem");
+    r.reset(mark.clone()).unwrap();
+    assert_eq!(r.position(), 2);
+    assert_eq!(r.read_exact(3).unwrap(), "rem");
+    assert_eq!(rem, "rem");
+
+    r.read_exact(20).unwrap();
+    assert_eq!(r.reset(mark), Err(ResetError));
+    assert_eq!(r.position(), 25);
+
+    // Bytes which bypass the buffer cannot be read again.
+    let s = "0123456789abcdefghij";
+    let mut r = SharedReader::new(s.as_bytes(), 8);
+    assert_eq!(r.read_exact(2).unwrap(), "01");
+    let mark = r.mark();
+    assert_eq!(r.skip(10).unwrap(), 10);
+    assert_eq!(r.reset(mark), Err(ResetError));
+    assert_eq!(r.position(), 12);
+    assert_eq!(r.read_to_end().unwrap(), "cdefghij");
+
+    let mut r = SharedReader::new(s.as_bytes(), 4);
+    assert_eq!(r.read_exact(4).unwrap(), "0123");
+    let mark = r.mark();
+    let mut out = [0; 8];
+    assert_eq!(r.read(&mut out).unwrap(), 8);
+    assert_eq!(&out, b"456789ab");
+    assert_eq!(r.reset(mark), Err(ResetError));
+    assert_eq!(r.position(), 12);
+}
+
+#[test]
+fn bytes_from_buf() {
+    let buf = BufMut::from(b"key=value".as_slice());
+    let value = Bytes::from_buf(buf.borrow(), 4..9);
+    assert_eq!(value, "value");
+    assert_eq!(value.buf(), buf.borrow());
+    assert_eq!(value.buf_range(), 4..9);
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| Bytes::from_buf(buf.borrow(), 4..10)));
+    assert!(res.is_err());
+}
+
+#[test]
+fn fill_to() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    assert_eq!(r.read_exact(6).unwrap(), "Lorem ");
+    let buf = r.buffer();
+    // The buffer grows as data arrives, so a whole read may be available.
+    assert_eq!(r.fill_to(12).unwrap(), 13);
+    assert_ne!(r.buffer(), buf);
+    assert_eq!(r.position(), 6);
+    assert_eq!(r.peek(12).unwrap(), &LOREM.as_bytes()[6..18]);
+    assert_eq!(r.fill_to(1).unwrap(), r.available_len());
+    // Fewer are available at EOF.
+    assert_eq!(r.fill_to(LOREM.len()).unwrap(), LOREM.len() - 6);
+    assert_eq!(r.position(), 6);
+
+    // A huge count on a short stream is not allocated up front.
+    let mut r = SharedReader::new("abc".as_bytes(), 4);
+    assert_eq!(r.peek(1 << 62).unwrap(), b"abc");
+    assert!(r.buffer().capacity() < 64);
+    let err = ReaderError::from(r.read_exact(1 << 62).unwrap_err());
+    assert!(matches!(
+        err,
+        ReaderError::UnexpectedEof {
+            expected: 0x4000_0000_0000_0000,
+            available: 3,
+        }
+    ));
+}
+
+#[test]
+fn last_line_copied() {
+    let mut b = LimitReader::new("one\ntwo\nthree\nfour\n".as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+    let mut copied = Vec::new();
+    for line in ["one\n", "two\n", "three\n", "four\n"] {
+        assert_eq!(r.read_line().unwrap(), line);
+        copied.push(r.last_line_copied());
+    }
+    // "two\n" fills the first buffer exactly and "three\n" starts a new one.
+    // Only "fo" of "four\n" fits in the second, so it is copied.
+    assert_eq!(copied, [false, false, false, true]);
+
+    // The line starts in an empty buffer, which is not full, so the bytes read
+    // into it before it fills are copied.
+    let mut b = LimitReader::new("abc\nlong line here\n".as_bytes(), 4);
+    let mut r = SharedReader::new(&mut b, 8);
+    assert_eq!(r.read_line().unwrap(), "abc\n");
+    assert!(!r.last_line_copied());
+    let first = r.buffer();
+    assert_eq!(r.read_line().unwrap(), "long line here\n");
+    assert_ne!(r.buffer(), first);
+    assert!(r.last_line_copied());
+}
+
+#[test]
+fn split_off() {
+    let mut buf = BufMut::new(8);
+    buf.append(b"first");
+    let bytes = buf.consume_bytes(3);
+    let first = buf.split_off();
+    assert_eq!(first.as_slice(), b"first");
+    assert_eq!(first, bytes.buf());
+    assert!(buf.available().is_empty());
+    assert_eq!(buf.borrow().capacity(), 8);
+    assert_ne!(buf.borrow(), first);
+
+    buf.append(b"second");
+    assert_eq!(buf.split_off().as_slice(), b"second");
+    assert_eq!(first.as_slice(), b"first");
+}
+
+#[test]
+fn with_allocator() {
+    let requested = Rc::new(RefCell::new(Vec::new()));
+    let make_buf = {
+        let requested = requested.clone();
+        move |capacity: usize| {
+            requested.borrow_mut().push(capacity);
+            BufMut::new(capacity.next_power_of_two())
+        }
+    };
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::with_allocator(&mut b, 6, make_buf);
+
+    assert_eq!(r.buffer().capacity(), 8);
+    let first = LOREM.split_inclusive('\n').next().unwrap();
+    assert_eq!(r.read_line().unwrap(), first);
+    let requested = requested.borrow();
+    assert_eq!(requested[0], 6);
+    assert!(requested.len() > 1);
+    assert!(r.buffer().capacity().is_power_of_two());
+
+    // A buffer which is too small panics, instead of truncating the line.
+    let mut r =
+        SharedReader::with_allocator("0123456789abcdef\n".as_bytes(), 4, |capacity: usize| {
+            BufMut::new(capacity.min(4))
+        });
+    let result = panic::catch_unwind(AssertUnwindSafe(|| r.read_line()));
+    assert!(result.is_err());
+
+    // So does a buffer which is not empty, instead of corrupting the line.
+    let filled = |capacity: usize| BufMut::from(vec![b'X'; capacity]);
+    let consumed = |capacity: usize| {
+        let mut buf = BufMut::new(capacity);
+        buf.append(&vec![0; capacity / 2]);
+        buf.consume(capacity / 2);
+        buf
+    };
+    let make_bufs: [fn(usize) -> BufMut; 2] = [filled, consumed];
+    for make_buf in make_bufs {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut r = SharedReader::with_allocator("0123456789abcdef\n".as_bytes(), 4, make_buf);
+            r.read_line()
+        }));
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn bytes_chunks() {
+    let bytes = BufMut::from(b"0123456789".as_slice()).consume_bytes(10);
+    let chunks = bytes.chunks(4).collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0], "0123");
+    assert_eq!(chunks[1], "4567");
+    assert_eq!(chunks[2], "89");
+    assert!(chunks.iter().all(|chunk| chunk.buf() == bytes.buf()));
+    assert_eq!(bytes.subslice(0..0).chunks(4).count(), 0);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| bytes.chunks(0).count()));
+    assert!(res.is_err());
+}
+
+#[test]
+fn pair_into_iter() {
+    let mut r = SharedReader::new("a\nb\nc\n".as_bytes(), 16);
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            lines.push(builder.read_line_bytes()?);
+        }
+        Ok(lines)
+    })
+    .unwrap();
+    let mut lines = Vec::new();
+    for line in &pair {
+        lines.push(utf8(line));
+    }
+    assert_eq!(lines, ["a\n", "b\n", "c\n"]);
+}
+
+#[test]
+fn read_all_lines() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 7);
+    let mut r = SharedReader::new(&mut b, 64);
+    let pair = r.read_all_lines().unwrap();
+
+    let lines = pair
+        .dependent()
+        .iter()
+        .map(|line| utf8(line))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, LOREM.split_inclusive('\n').collect::<Vec<_>>());
+    let owner = pair.owner();
+    assert!(owner.len() > 1);
+    for (i, buf) in owner.iter().enumerate() {
+        assert!(!owner[..i].contains(buf));
+    }
+    for line in pair.dependent() {
+        assert!(owner.contains(&line.buf()));
+    }
+}
+
+#[test]
+fn buf_mut_filled() {
+    let mut buf = BufMut::new(8);
+    assert!(buf.filled().is_empty());
+    buf.append(b"abcde");
+    buf.consume(2);
+    assert_eq!(buf.filled(), b"abcde");
+    assert_eq!(buf.filled(), [buf.consumed(), buf.available()].concat());
+}
+
+#[test]
+fn growth_capacity_overflow() {
+    let huge = isize::MAX as usize;
+    assert_eq!(GrowthStrategy::Double.capacity(huge, 8), usize::MAX - 1);
+    assert_eq!(
+        GrowthStrategy::Double.capacity(usize::MAX / 2 + 1, 8),
+        usize::MAX
+    );
+    assert_eq!(
+        GrowthStrategy::Additive(usize::MAX).capacity(huge, 8),
+        usize::MAX
+    );
+    assert_eq!(GrowthStrategy::Fixed(16).capacity(huge, 8), huge + 16);
+    assert_eq!(GrowthStrategy::Double.capacity(3, 8), 8);
+    assert_eq!(GrowthStrategy::Double.capacity(5, 8), 10);
+}
+
+#[test]
+fn growth_capacity_room() {
+    // Lines longer than a fixed capacity grow by that amount instead of by
+    // one byte, so reads are not quadratic.
+    assert_eq!(GrowthStrategy::Fixed(16).capacity(4, 8), 16);
+    assert_eq!(GrowthStrategy::Fixed(16).capacity(16, 8), 32);
+    assert_eq!(GrowthStrategy::Fixed(16).capacity(40, 8), 56);
+    assert_eq!(GrowthStrategy::Fixed(0).capacity(40, 8), 41);
+    assert_eq!(GrowthStrategy::Additive(0).capacity(40, 8), 41);
+    assert_eq!(GrowthStrategy::Additive(8).capacity(40, 8), 48);
+
+    let line = "x".repeat(20_000) + "\n";
+    let mut r = SharedReader::with_growth(line.as_bytes(), 16, GrowthStrategy::Fixed(16));
+    assert_eq!(r.read_line().unwrap(), line.as_str());
+}
+
+#[test]
+fn bytes_read() {
+    let mut b = LimitReader::new(LOREM.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+    assert_eq!(r.bytes_read(), 0);
+    assert_eq!(r.read_exact(2).unwrap(), "Lo");
+    assert_eq!(r.bytes_read(), 5);
+    assert_eq!(r.position(), 2);
+    assert_eq!(r.read_exact(4).unwrap(), "rem ");
+    assert_eq!(r.bytes_read(), 8);
+    assert_eq!(r.skip(10).unwrap(), 10);
+    assert_eq!(r.bytes_read(), 16);
+    let mut out = [0; 3];
+    assert_eq!(io::Read::read(&mut r, &mut out).unwrap(), 3);
+    assert_eq!(r.bytes_read(), 19);
+    r.read_to_end().unwrap();
+    assert_eq!(r.bytes_read(), LOREM.len() as u64);
+}
+
+#[test]
+fn bytes_read_impl() {
+    let mut r = SharedReader::new("header\nbody".as_bytes(), 16);
+    r.read_line().unwrap();
+    let body = r.read_to_end().unwrap();
+
+    let mut segment = body.clone();
+    let mut out = [0; 3];
+    assert_eq!(segment.read(&mut out).unwrap(), 3);
+    assert_eq!(&out, b"bod");
+    let mut rest = String::new();
+    segment.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "y");
+    assert!(segment.is_empty());
+    assert_eq!(segment.read(&mut out).unwrap(), 0);
+    assert_eq!(body, "body");
+}
+
+#[test]
+fn read_line_utf8() {
+    let mut r = SharedReader::new(b"caf\xc3\xa9\nbad \xff\n".as_slice(), 4);
+    assert_eq!(r.read_line_utf8().unwrap(), Ok("café\n"));
+    let line = r.read_line_utf8().unwrap().unwrap_err();
+    assert_eq!(line.slice(), b"bad \xff\n");
+    assert_eq!(r.read_line_utf8().unwrap(), Ok(""));
+}
+
+#[test]
+// The hashed slice is persistent, despite the `UnsafeCell` in the buffer.
+#[allow(clippy::mutable_key_type)]
+fn pair_hash_map() {
+    // A `HashMap<&'a [u8], &'a [u8]>` dependent cannot be returned from
+    // `BufPair::new`, as its doc shows, so the map holds `Bytes` instead.
+    let s = "author: Author\ncommitter: Committer\ntree: 0123\n";
+    let mut b = LimitReader::new(s.as_bytes(), 5);
+    let mut r = SharedReader::new(&mut b, 16);
+    let pair = BufPair::new(&mut r, |builder| -> io::Result<_> {
+        let mut fields = HashMap::new();
+        for _ in 0..3 {
+            let line = builder.read_line_bytes()?.trim_ascii_end();
+            let colon = line.iter().position(|&b| b == b':').unwrap();
+            let key = line.subslice(0..colon);
+            let value = line.subslice(colon + 1..line.len()).trim_ascii_start();
+            fields.insert(key, value);
+        }
+        Ok(fields)
+    })
+    .unwrap();
+
+    let fields = pair.dependent();
+    assert_eq!(fields[b"author".as_slice()], "Author");
+    assert_eq!(fields[b"committer".as_slice()], "Committer");
+    assert_eq!(fields[b"tree".as_slice()], "0123");
+    for (key, value) in fields {
+        assert!(pair.owner().contains(&key.buf()));
+        assert!(pair.owner().contains(&value.buf()));
+    }
+}
+
+#[test]
+fn read_until_exclusive() {
+    let mut b = LimitReader::new(b"a,bc,,d".as_slice(), 2);
+    let mut r = SharedReader::new(&mut b, 4);
+    assert_eq!(r.read_until_exclusive(b',').unwrap(), "a");
+    assert_eq!(r.position(), 2);
+    assert_eq!(r.read_until_inclusive(b',').unwrap(), "bc,");
+    assert_eq!(r.read_until_exclusive(b',').unwrap(), "");
+    assert_eq!(r.read_until_exclusive(b',').unwrap(), "d");
+    assert_eq!(r.read_until_exclusive(b',').unwrap(), "");
+    assert_eq!(r.position(), 7);
+}
+
+#[test]
+fn peek_line_interleaved() {
+    /// A reader which counts the calls to `read`.
+    struct CountReads<R>(R, Rc<Cell<usize>>);
+
+    impl<R: Read> Read for CountReads<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1.set(self.1.get() + 1);
+            self.0.read(buf)
+        }
+    }
+
+    // The last line is unterminated and, for `Any`, ends with CR, so finding
+    // its end needs a read which reaches EOF.
+    for s in ["a\nbcdef\n\nghi\r\njk", "a\r\n\rb\r"] {
+        for line_ending in [LineEnding::Lf, LineEnding::Any] {
+            let reads = Rc::new(Cell::new(0));
+            let b = CountReads(LimitReader::new(s.as_bytes(), 3), reads.clone());
+            let mut r = SharedReader::with_line_ending(b, 4, line_ending);
+            let mut out = Vec::new();
+            for i in 0.. {
+                let peeked = r.peek_line().unwrap().to_vec();
+                let read_count = reads.get();
+                if i % 2 == 0 {
+                    assert_eq!(r.peek_line().unwrap(), peeked);
+                }
+                let line = r.read_line().unwrap();
+                assert_eq!(line, peeked.as_slice());
+                assert_eq!(reads.get(), read_count);
+                if line.is_empty() {
+                    break;
+                }
+                out.extend_from_slice(&line);
+                assert_eq!(r.position(), out.len());
+            }
+            assert_eq!(out, s.as_bytes());
+        }
+    }
+}
+
+#[test]
+fn assume_filled() {
+    let mut buf = BufMut::new(8);
+    buf.append(b"ab");
+    assert_eq!(buf.capacity(), 8);
+    let ptr = buf.as_mut_ptr();
+    // Write like a C library would, which reports how much it wrote.
+    let n = unsafe {
+        ptr::copy_nonoverlapping(b"cde".as_ptr(), ptr, 3);
+        3
+    };
+    unsafe { buf.assume_filled(n) };
+    assert_eq!(buf.filled(), b"abcde");
+    assert_eq!(buf.unfilled(), [0; 3]);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| unsafe { buf.assume_filled(4) }));
+    assert!(result.is_err());
+    assert_eq!(buf.filled(), b"abcde");
+}
+
+#[test]
+fn max_buffers() {
+    let s = "abc\nabcdefghijklmnopqrstuvwxyz\nxyz";
+    let mut b = LimitReader::new(s.as_bytes(), 1);
+    let mut r = SharedReader::with_max_buffers(&mut b, 4, 3);
+    // The first line fits in the initial buffer, so it needs no new buffers.
+    assert_eq!(r.read_line().unwrap(), "abc\n");
+    // The buffers for the long line double from 4 to 8 to 16, which then is
+    // still too small.
+    let err = r.read_line().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        ReaderError::from(err),
+        ReaderError::TooManyBuffers { limit: 3 }
+    ));
+    // The long line is discarded, so reading resumes after it.
+    assert_eq!(r.read_line().unwrap(), "xyz");
+
+    // One more buffer, with a capacity of 32, fits it.
+    let mut b = LimitReader::new(s.as_bytes(), 1);
+    let mut r = SharedReader::with_max_buffers(&mut b, 4, 4);
+    assert_eq!(r.read_line().unwrap(), "abc\n");
+    assert_eq!(r.read_line().unwrap(), "abcdefghijklmnopqrstuvwxyz\n");
+    assert_eq!(r.read_line().unwrap(), "xyz");
+}
+
+#[test]
+fn setters() {
+    let s = "a\nb\r\nmedium line\r\nshort\r\nlong line, through many buffers\r\n\
+        another long line, in buffers\r\n";
+    let mut b = LimitReader::new(s.as_bytes(), 2);
+    let mut r = SharedReader::with_max_line(&mut b, 4, 16);
+    r.set_line_ending(LineEnding::CrLf);
+    r.set_max_buffers(Some(2));
+    r.set_growth(GrowthStrategy::Additive(4));
+    assert_eq!(r.read_line().unwrap(), "a\nb\r\n");
+    let err = ReaderError::from(r.read_line().unwrap_err());
+    assert!(matches!(err, ReaderError::TooManyBuffers { limit: 2 }));
+
+    r.set_max_buffers(None);
+    assert_eq!(r.read_line().unwrap(), "short\r\n");
+    let err = ReaderError::from(r.read_line().unwrap_err());
+    assert!(matches!(err, ReaderError::LineTooLong { limit: 16 }));
+
+    r.set_max_line(None);
+    assert_eq!(r.read_line().unwrap(), "another long line, in buffers\r\n");
+}
+
+#[test]
+fn extend() {
+    let mut buf = BufMut::new(4);
+    buf.extend([b"abc".as_slice(), b"", b"defgh"]);
+    assert_eq!(buf.filled(), b"abcdefgh");
+
+    let shared = buf.borrow();
+    buf.extend(b"ijk".iter().copied());
+    assert_eq!(buf.filled(), b"abcdefghijk");
+    assert_eq!(shared.as_slice(), b"abcdefgh");
+}
+
+#[test]
+fn compact_buffer() {
+    let mut b = LimitReader::new(b"ab\ncd\nef\n".as_slice(), 5);
+    let mut r = SharedReader::new(&mut b, 8);
+
+    let line = r.read_line().unwrap();
+    r.compact_buffer();
+    assert_eq!(r.consumed_len(), 3);
+    assert_eq!(line, "ab\n");
+
+    drop(line);
+    r.compact_buffer();
+    assert_eq!(r.consumed_len(), 0);
+    assert_eq!(r.available_len(), 2);
+    assert_eq!(r.position(), 3);
+    assert_eq!(r.read_line().unwrap(), "cd\n");
+    assert_eq!(r.read_line().unwrap(), "ef\n");
+    assert_eq!(r.position(), 9);
+}
+
+#[test]
+fn bytes_strip_prefix() {
+    let s = "author: Author\ncommitter: Committer";
+    let mut b = LimitReader::new(s.as_bytes(), 8);
+    let mut r = SharedReader::new(&mut b, 100);
+
+    let parse = |line: Bytes, key: &[u8]| {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_prefix(key)
+    };
+    let line = r.read_line().unwrap();
+    assert!(line.starts_with(b"author: ") && line.ends_with(b"\n"));
+    let author = parse(line.clone(), b"author: ").unwrap();
+    let committer = parse(r.read_line().unwrap(), b"committer: ").unwrap();
+    assert_eq!(author, "Author");
+    assert_eq!(committer, "Committer");
+    assert_eq!(author.buf(), line.buf());
+    assert_eq!(author.buf_range(), 8..14);
+
+    assert!(parse(line, b"committer: ").is_none());
+    assert!(author.strip_suffix(b"x").is_none());
+    assert_eq!(author.strip_prefix(b"").unwrap(), "Author");
+    assert_eq!(author.strip_suffix(b"Author").unwrap(), "");
+}
+
+#[test]
+fn max_line_resumes() {
+    let s = "ok\nthis line is too long\nnext\n";
+    let mut b = LimitReader::new(s.as_bytes(), 3);
+    let mut r = SharedReader::with_max_line(&mut b, 4, 8);
+    let lines = r
+        .lines()
+        .map(|line| line.map(|line| line.to_vec()).map_err(ReaderError::from))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].as_ref().unwrap(), b"ok\n");
+    assert!(matches!(
+        lines[1],
+        Err(ReaderError::LineTooLong { limit: 8 })
+    ));
+    assert_eq!(lines[2].as_ref().unwrap(), b"next\n");
+    assert_eq!(r.position(), s.len());
+
+    // A delimiter which straddles the discarded reads is still found.
+    let s = "long data, then --end-- and the rest";
+    let mut b = LimitReader::new(s.as_bytes(), 3);
+    let mut r = SharedReader::with_max_line(&mut b, 4, 8);
+    let err = r.read_until_seq(b"--end--").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(r.read_to_end().unwrap(), " and the rest");
+
+    // The line is discarded through EOF, when it has no line ending.
+    let mut r = SharedReader::with_line_ending("overlong\r".as_bytes(), 4, LineEnding::Any);
+    r.set_max_line(Some(4));
+    assert!(r.read_line().is_err());
+    assert_eq!(r.read_line().unwrap(), "");
+    assert_eq!(r.position(), 9);
+}
+
+#[test]
+fn unfilled_window() {
+    let mut buf = BufMut::<u8>::new(1 << 20);
+    assert_eq!(buf.unfilled().len(), 8192);
+    buf.fill(100);
+    assert_eq!(buf.unfilled().len(), 8192);
+    buf.fill(8192);
+    assert_eq!(buf.filled().len(), 8292);
+    assert_eq!(buf.unfilled().len(), 8192);
+    // The whole buffer is still reachable, one window at a time.
+    while !buf.unfilled().is_empty() {
+        let len = buf.unfilled().len();
+        buf.fill(len);
+    }
+    assert_eq!(buf.filled().len(), 1 << 20);
+
+    let mut buf = ArcBufMut::<u8>::new(1 << 20);
+    assert_eq!(buf.unfilled().len(), 8192);
+    let mut buf = BufMut::<u8>::new(16);
+    assert_eq!(buf.unfilled().len(), 16);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialize() {
+    let mut r = SharedReader::new("one\ntwo\n".as_bytes(), 4);
+    let line = r.read_line().unwrap();
+    assert_eq!(serde_json::to_string(&line).unwrap(), "[111,110,101,10]");
+
+    let lines = r.read_all_lines().unwrap();
+    assert_eq!(serde_json::to_string(&lines).unwrap(), "[[116,119,111,10]]");
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn bytes_crate() {
+    use bytes::Buf;
+
+    let mut r = SharedReader::new(b"\x00\x05hello, world".as_slice(), 16);
+    let mut frame = r.read_exact(14).unwrap();
+    let whole = frame.clone();
+    assert_eq!(frame.get_u16(), 5);
+    assert_eq!(frame.remaining(), 12);
+    assert_eq!(frame.chunk(), b"hello, world");
+    frame.advance(7);
+    assert_eq!(frame, "world");
+    assert_eq!(whole.len(), 14);
+
+    let copied = frame.into_bytes_crate();
+    assert_eq!(copied, bytes::Bytes::from_static(b"world"));
 }
 
 #[track_caller]
@@ -106,7 +1752,3 @@ fn utf8(s: &[u8]) -> &str {
         Cow::Owned(lossy) => panic!("not UTF-8: {lossy:?} ({s:?})"),
     }
 }
-
-fn strip_lf(line: &[u8]) -> &[u8] {
-    line.strip_suffix(b"\n").unwrap_or(line)
-}